@@ -0,0 +1,465 @@
+//! Writes the documents and cards of the database as an Anki package.
+//!
+//! Every document becomes a deck. The cards use a note type with the
+//! question, the answer and the context of the document as fields. The
+//! schedule and the review log are kept, so the progress carries over.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+    process,
+};
+
+use chrono::{NaiveDate, Utc};
+use rusqlite::Connection;
+use sea_query::{Query, SqliteQueryBuilder};
+use serde_json::{json, Value};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+sea_query::sea_query_driver_rusqlite!();
+use sea_query_driver_rusqlite::RusqliteValues;
+
+use crate::{
+    database::Database,
+    org::FlashCard,
+    sr::{Grade, Review},
+};
+
+use super::{
+    org_to_html,
+    schema::{self, Cards, Col, Notes, Revlog},
+    AnkiError, COLLECTION, FIELD_SEPARATOR,
+};
+
+const MODEL_NAME: &str = "Basic (flashcards)";
+const FIELDS: [&str; 3] = ["Front", "Back", "Context"];
+
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub decks: usize,
+    pub cards: usize,
+    pub reviews: usize,
+}
+
+impl fmt::Display for ExportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} decks, {} cards, {} reviews",
+            self.decks, self.cards, self.reviews
+        )
+    }
+}
+
+/// Writes every document with cards into the package at `path`.
+pub fn export(db: &Database, path: &Path) -> Result<ExportSummary, AnkiError> {
+    let collection = std::env::temp_dir().join(format!("flashcards-{}.anki2", process::id()));
+    let _ = fs::remove_file(&collection);
+    let result = write_collection(db, &collection)
+        .and_then(|summary| write_package(&collection, path).map(|_| summary));
+    let _ = fs::remove_file(&collection);
+    result
+}
+
+fn write_package(collection: &Path, path: &Path) -> Result<(), AnkiError> {
+    let mut contents = vec![];
+    File::open(collection)?.read_to_end(&mut contents)?;
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(File::create(path)?);
+    zip.start_file(COLLECTION, options)?;
+    zip.write_all(&contents)?;
+    // There are no media files, but Anki expects the list.
+    zip.start_file("media", options)?;
+    zip.write_all(b"{}")?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Anki checks for duplicates with the first 8 digits of the SHA-1 of the
+/// first field.
+fn checksum(field: &str) -> i64 {
+    let digest = sha1::Sha1::from(field).digest().bytes();
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as i64
+}
+
+/// The answer buttons of Anki, from 1 (Again) to 4 (Easy).
+fn button(grade: Grade) -> i64 {
+    match grade {
+        Grade::Again => 1,
+        Grade::Hard => 2,
+        Grade::Good => 3,
+        Grade::Easy => 4,
+    }
+}
+
+/// A card that was never answered is a new card in Anki.
+fn is_new(card: &FlashCard) -> bool {
+    card.get_stats().last_review.is_none() && card.get_stats().interval == 0
+}
+
+fn write_collection(db: &Database, path: &Path) -> Result<ExportSummary, AnkiError> {
+    let conn = Connection::open(path)?;
+    schema::create_tables(&conn)?;
+
+    let now = Utc::now();
+    let now_ms = now.timestamp_millis();
+    let today = now.date().naive_local();
+
+    let mut decks = vec![];
+    for document in db.load_documents()? {
+        let cards = db.document_flashcards(document.get_id())?;
+        if !cards.is_empty() {
+            decks.push((document, cards));
+        }
+    }
+    // The due dates of Anki are days since the creation of the collection,
+    // so it is created before the first scheduled card.
+    let created = decks
+        .iter()
+        .flat_map(|(_, cards)| cards.iter().filter_map(|card| card.get_scheduled()))
+        .fold(today, NaiveDate::min);
+
+    let mut reviews: HashMap<i64, Vec<Review>> = HashMap::new();
+    for review in db.load_reviews()? {
+        reviews.entry(review.card_id).or_default().push(review);
+    }
+
+    let model_id = now_ms;
+    let mut summary = ExportSummary::default();
+    let mut deck_names = vec![];
+    let mut review_ids = HashSet::new();
+    let mut next_id = now_ms;
+    for (deck, (document, cards)) in decks.iter().enumerate() {
+        let deck_id = now_ms + 1 + deck as i64;
+        deck_names.push((deck_id, document.get_title().to_owned()));
+        let context = org_to_html(document.get_contents());
+
+        for card in cards {
+            next_id += 1;
+            let front = org_to_html(card.get_questions());
+            let fields = [front.as_str(), &org_to_html(card.get_answers()), &context]
+                .join(&FIELD_SEPARATOR.to_string());
+            // The ID of a card keeps it the same note when it is exported again.
+            let guid = match card.get_uid() {
+                Some(uid) => uid.to_owned(),
+                None => format!("flashcards-{}", card.get_id()),
+            };
+            let (sql, values) = Query::insert()
+                .into_table(Notes::Table)
+                .columns(vec![
+                    Notes::Id,
+                    Notes::Guid,
+                    Notes::Mid,
+                    Notes::Mod,
+                    Notes::Usn,
+                    Notes::Tags,
+                    Notes::Flds,
+                    Notes::Sfld,
+                    Notes::Csum,
+                    Notes::Flags,
+                    Notes::Data,
+                ])
+                .values_panic(vec![
+                    next_id.into(),
+                    guid.into(),
+                    model_id.into(),
+                    now.timestamp().into(),
+                    (-1).into(),
+                    "".into(),
+                    fields.into(),
+                    card.get_questions().into(),
+                    checksum(card.get_questions()).into(),
+                    0.into(),
+                    "".into(),
+                ])
+                .build(SqliteQueryBuilder);
+            conn.execute(
+                sql.as_str(),
+                RusqliteValues::from(values).as_params().as_slice(),
+            )?;
+
+            let stats = card.get_stats();
+            let history = reviews
+                .get(&card.get_id())
+                .map(|r| r.as_slice())
+                .unwrap_or(&[]);
+            let lapses = history
+                .iter()
+                .filter(|review| review.grade == Grade::Again && review.previous_interval > 0)
+                .count() as i64;
+            // Anki keeps the ease of new cards at zero until they are learned.
+            let (kind, due, factor) = if is_new(card) {
+                (0, summary.cards as i64, 0)
+            } else {
+                let scheduled = card.get_scheduled().unwrap_or(today);
+                (
+                    2,
+                    (scheduled - created).num_days(),
+                    (stats.difficultly * 1000.0).round() as i64,
+                )
+            };
+            let (sql, values) = Query::insert()
+                .into_table(Cards::Table)
+                .columns(vec![
+                    Cards::Id,
+                    Cards::Nid,
+                    Cards::Did,
+                    Cards::Ord,
+                    Cards::Mod,
+                    Cards::Usn,
+                    Cards::Type,
+                    Cards::Queue,
+                    Cards::Due,
+                    Cards::Ivl,
+                    Cards::Factor,
+                    Cards::Reps,
+                    Cards::Lapses,
+                    Cards::Left,
+                    Cards::Odue,
+                    Cards::Odid,
+                    Cards::Flags,
+                    Cards::Data,
+                ])
+                .values_panic(vec![
+                    next_id.into(),
+                    next_id.into(),
+                    deck_id.into(),
+                    0.into(),
+                    now.timestamp().into(),
+                    (-1).into(),
+                    kind.into(),
+                    kind.into(),
+                    due.into(),
+                    stats.interval.into(),
+                    factor.into(),
+                    (history.len() as i64).max(stats.num_reps as i64).into(),
+                    lapses.into(),
+                    0.into(),
+                    0.into(),
+                    0.into(),
+                    0.into(),
+                    "".into(),
+                ])
+                .build(SqliteQueryBuilder);
+            conn.execute(
+                sql.as_str(),
+                RusqliteValues::from(values).as_params().as_slice(),
+            )?;
+            summary.cards += 1;
+
+            for review in history {
+                // The log is keyed by the time of the answer in milliseconds.
+                let mut id = review.reviewed_at.timestamp_millis();
+                while !review_ids.insert(id) {
+                    id += 1;
+                }
+                let (sql, values) = Query::insert()
+                    .into_table(Revlog::Table)
+                    .columns(vec![
+                        Revlog::Id,
+                        Revlog::Cid,
+                        Revlog::Usn,
+                        Revlog::Ease,
+                        Revlog::Ivl,
+                        Revlog::LastIvl,
+                        Revlog::Factor,
+                        Revlog::Time,
+                        Revlog::Type,
+                    ])
+                    .values_panic(vec![
+                        id.into(),
+                        next_id.into(),
+                        (-1).into(),
+                        button(review.grade).into(),
+                        review.new_interval.into(),
+                        review.previous_interval.into(),
+                        ((review.ease * 1000.0).round() as i64).into(),
+                        review.duration.min(60_000).into(),
+                        // Answers of a new card are learning steps.
+                        (if review.previous_interval == 0 { 0 } else { 1 }).into(),
+                    ])
+                    .build(SqliteQueryBuilder);
+                conn.execute(
+                    sql.as_str(),
+                    RusqliteValues::from(values).as_params().as_slice(),
+                )?;
+                summary.reviews += 1;
+            }
+        }
+    }
+    summary.decks = decks.len();
+
+    let created_at = created.and_hms(0, 0, 0).timestamp();
+    let (sql, values) = Query::insert()
+        .into_table(Col::Table)
+        .columns(vec![
+            Col::Id,
+            Col::Crt,
+            Col::Mod,
+            Col::Scm,
+            Col::Ver,
+            Col::Dty,
+            Col::Usn,
+            Col::Ls,
+            Col::Conf,
+            Col::Models,
+            Col::Decks,
+            Col::Dconf,
+            Col::Tags,
+        ])
+        .values_panic(vec![
+            1.into(),
+            created_at.into(),
+            now_ms.into(),
+            now_ms.into(),
+            11.into(),
+            0.into(),
+            0.into(),
+            0.into(),
+            config(model_id).to_string().into(),
+            models(model_id, now.timestamp()).to_string().into(),
+            deck_list(&deck_names, now.timestamp()).to_string().into(),
+            deck_options(now.timestamp()).to_string().into(),
+            "{}".into(),
+        ])
+        .build(SqliteQueryBuilder);
+    conn.execute(
+        sql.as_str(),
+        RusqliteValues::from(values).as_params().as_slice(),
+    )?;
+    Ok(summary)
+}
+
+fn config(model_id: i64) -> Value {
+    json!({
+        "nextPos": 1,
+        "estTimes": true,
+        "activeDecks": [1],
+        "sortType": "noteFld",
+        "timeLim": 0,
+        "sortBackwards": false,
+        "addToCur": true,
+        "curDeck": 1,
+        "newSpread": 0,
+        "dueCounts": true,
+        "curModel": model_id.to_string(),
+        "collapseTime": 1200,
+    })
+}
+
+fn models(model_id: i64, modified: i64) -> Value {
+    let fields: Vec<Value> = FIELDS
+        .iter()
+        .enumerate()
+        .map(|(ord, name)| {
+            json!({
+                "name": name,
+                "ord": ord,
+                "sticky": false,
+                "rtl": false,
+                "font": "Arial",
+                "size": 20,
+                "media": [],
+            })
+        })
+        .collect();
+    let model = json!({
+        "id": model_id,
+        "name": MODEL_NAME,
+        "type": 0,
+        "mod": modified,
+        "usn": -1,
+        "sortf": 0,
+        "did": 1,
+        "tmpls": [{
+            "name": "Card 1",
+            "ord": 0,
+            "qfmt": "{{Front}}",
+            "afmt": "{{FrontSide}}\n\n<hr id=answer>\n\n{{Back}}\n\n<div class=context>{{Context}}</div>",
+            "did": null,
+            "bqfmt": "",
+            "bafmt": "",
+        }],
+        "flds": fields,
+        "css": ".card { font-family: serif; font-size: 20px; text-align: left; }\n.context { margin-top: 2em; font-size: 16px; color: grey; }",
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+        "latexsvg": false,
+        "req": [[0, "any", [0]]],
+        "tags": [],
+        "vers": [],
+    });
+    json!({ model_id.to_string(): model })
+}
+
+fn deck(id: i64, name: &str, modified: i64) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "mod": modified,
+        "usn": -1,
+        "lrnToday": [0, 0],
+        "revToday": [0, 0],
+        "newToday": [0, 0],
+        "timeToday": [0, 0],
+        "collapsed": false,
+        "browserCollapsed": false,
+        "desc": "",
+        "dyn": 0,
+        "conf": 1,
+        "extendNew": 0,
+        "extendRev": 0,
+    })
+}
+
+fn deck_list(decks: &[(i64, String)], modified: i64) -> Value {
+    let mut list = serde_json::Map::new();
+    list.insert("1".to_owned(), deck(1, "Default", modified));
+    for (id, name) in decks {
+        list.insert(id.to_string(), deck(*id, name, modified));
+    }
+    Value::Object(list)
+}
+
+fn deck_options(modified: i64) -> Value {
+    json!({
+        "1": {
+            "id": 1,
+            "name": "Default",
+            "mod": modified,
+            "usn": -1,
+            "maxTaken": 60,
+            "autoplay": true,
+            "timer": 0,
+            "replayq": true,
+            "dyn": false,
+            "new": {
+                "bury": false,
+                "delays": [1.0, 10.0],
+                "initialFactor": 2500,
+                "ints": [1, 4, 0],
+                "order": 1,
+                "perDay": 20,
+            },
+            "lapse": {
+                "delays": [10.0],
+                "leechAction": 1,
+                "leechFails": 8,
+                "minInt": 1,
+                "mult": 0.0,
+            },
+            "rev": {
+                "bury": false,
+                "ease4": 1.3,
+                "ivlFct": 1.0,
+                "maxIvl": 36500,
+                "perDay": 200,
+                "hardFactor": 1.2,
+            },
+        }
+    })
+}
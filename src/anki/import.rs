@@ -0,0 +1,626 @@
+//! Reads the decks of an Anki package into the database.
+//!
+//! Every deck becomes a document and every card a flashcard with the ID of
+//! its note, so a package can be imported again without duplicates. The
+//! fields are converted from HTML to org, and the schedule and the review log
+//! carry over.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+    process,
+};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use fallible_iterator::FallibleIterator;
+use rusqlite::Connection;
+use sea_query::{Expr, Order, Query, SqliteQueryBuilder};
+use serde_json::Value;
+use zip::ZipArchive;
+
+sea_query::sea_query_driver_rusqlite!();
+use sea_query_driver_rusqlite::RusqliteValues;
+
+use crate::{
+    cloze,
+    database::{Database, FlashCards},
+    org::FlashCard,
+    sr::{Grade, Review, Stats},
+};
+
+use super::{
+    html_to_org,
+    schema::{Cards, Col, Notes, Revlog},
+    AnkiError, COLLECTION, FIELD_SEPARATOR,
+};
+
+/// Newer versions of Anki write the collection under this name, next to a
+/// legacy one that only asks to update Anki.
+const COLLECTION_21: &str = "collection.anki21";
+/// The zstd compressed collection of Anki 2.1.50 and later.
+const COLLECTION_21B: &str = "collection.anki21b";
+
+/// The field of the exported notes that holds the context of the document.
+const CONTEXT_FIELD: &str = "Context";
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub decks: usize,
+    pub cards: usize,
+    pub skipped: usize,
+    pub reviews: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} decks, {} cards, {} reviews, {} cards already imported",
+            self.decks, self.cards, self.reviews, self.skipped
+        )
+    }
+}
+
+struct Model {
+    cloze: bool,
+    fields: Vec<String>,
+}
+
+struct AnkiCard {
+    id: i64,
+    deck: i64,
+    ord: i64,
+    kind: i64,
+    due: i64,
+    interval: i64,
+    factor: i64,
+    reps: i64,
+    lapses: i64,
+    guid: String,
+    model: i64,
+    fields: String,
+}
+
+/// Imports the decks of the `.apkg` or `.colpkg` package at `path`.
+pub fn import_package(db: &Database, path: &Path) -> Result<ImportSummary, AnkiError> {
+    let collection =
+        std::env::temp_dir().join(format!("flashcards-import-{}.anki2", process::id()));
+    let result = extract_collection(path, &collection).and_then(|_| {
+        let conn = Connection::open(&collection)?;
+        read_collection(db, path, &conn)
+    });
+    let _ = fs::remove_file(&collection);
+    result
+}
+
+fn extract_collection(path: &Path, collection: &Path) -> Result<(), AnkiError> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let names: Vec<String> = zip.file_names().map(|name| name.to_owned()).collect();
+    let name = [COLLECTION_21, COLLECTION]
+        .iter()
+        .find(|name| names.iter().any(|n| n == *name));
+    let name = match name {
+        Some(name) => *name,
+        None if names.iter().any(|n| n == COLLECTION_21B) => {
+            return Err(AnkiError::Unsupported(
+                "the collection is compressed, export it with support for older Anki versions",
+            ))
+        }
+        None => return Err(AnkiError::Unsupported("the package holds no collection")),
+    };
+    let mut contents = vec![];
+    zip.by_name(name)?.read_to_end(&mut contents)?;
+    fs::write(collection, contents)?;
+    Ok(())
+}
+
+fn read_collection(
+    db: &Database,
+    path: &Path,
+    conn: &Connection,
+) -> Result<ImportSummary, AnkiError> {
+    let (sql, values) = Query::select()
+        .columns(vec![Col::Crt, Col::Models, Col::Decks])
+        .from(Col::Table)
+        .limit(1)
+        .build(SqliteQueryBuilder);
+    let (created, models, decks): (i64, String, String) = conn.query_row(
+        sql.as_str(),
+        RusqliteValues::from(values).as_params().as_slice(),
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let created = NaiveDateTime::from_timestamp(created, 0).date();
+    let models = parse_models(&models)?;
+    let decks = parse_decks(&decks)?;
+
+    let file_id = db.insert_file(path)?;
+    let today = Utc::now().date().naive_local();
+    let mut summary = ImportSummary::default();
+    let mut documents: HashMap<i64, i64> = HashMap::new();
+    let mut card_ids: HashMap<i64, i64> = HashMap::new();
+    for card in load_cards(conn)? {
+        let model = match models.get(&card.model) {
+            Some(model) => model,
+            None => continue,
+        };
+        let fields: Vec<&str> = card.fields.split(FIELD_SEPARATOR).collect();
+        let (uid, question, answer) = match card_text(&card, model, &fields) {
+            Some(text) => text,
+            None => continue,
+        };
+
+        let existing = match db.find_flashcard(FlashCards::Uid, &uid)? {
+            Some(stored) => Some(stored),
+            None => db.find_flashcard(FlashCards::Questions, &question)?,
+        };
+        if existing.is_some() {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let doc_id = match documents.get(&card.deck) {
+            Some(id) => *id,
+            None => {
+                let name = decks
+                    .get(&card.deck)
+                    .map(|name| name.as_str())
+                    .unwrap_or("Default");
+                let context = model
+                    .fields
+                    .iter()
+                    .position(|field| field == CONTEXT_FIELD)
+                    .and_then(|idx| fields.get(idx))
+                    .map(|html| html_to_org(html));
+                let id = match db.find_titled_document(name)? {
+                    Some(id) => id,
+                    None => db.insert_documents(
+                        &context_content(name, context.as_deref()),
+                        name,
+                        file_id,
+                    )?,
+                };
+                documents.insert(card.deck, id);
+                summary.decks += 1;
+                id
+            }
+        };
+
+        let (stats, scheduled) = schedule(&card, created, today);
+        let mut flashcard = FlashCard::from_db(&question, &answer, 0, Some(uid), doc_id, stats);
+        flashcard.set_scheduled(scheduled);
+        card_ids.insert(card.id, db.insert_flashcard(&flashcard)?);
+        summary.cards += 1;
+    }
+
+    if has_table(conn, "revlog")? {
+        let reviews = load_reviews(conn, &card_ids)?;
+        summary.reviews = reviews.len();
+        db.insert_reviews(&reviews)?;
+    }
+    Ok(summary)
+}
+
+fn invalid(err: serde_json::Error) -> AnkiError {
+    AnkiError::Io(io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The note types of the collection, with their fields in order.
+fn parse_models(json: &str) -> Result<HashMap<i64, Model>, AnkiError> {
+    let models: HashMap<String, Value> = serde_json::from_str(json).map_err(invalid)?;
+    let mut parsed = HashMap::new();
+    for (id, model) in models {
+        let id = match id.parse::<i64>() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let mut fields: Vec<(i64, String)> = model["flds"]
+            .as_array()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|field| {
+                        (
+                            field["ord"].as_i64().unwrap_or(0),
+                            field["name"].as_str().unwrap_or("").to_owned(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        fields.sort();
+        parsed.insert(
+            id,
+            Model {
+                cloze: model["type"].as_i64() == Some(1),
+                fields: fields.into_iter().map(|(_, name)| name).collect(),
+            },
+        );
+    }
+    Ok(parsed)
+}
+
+fn parse_decks(json: &str) -> Result<HashMap<i64, String>, AnkiError> {
+    let decks: HashMap<String, Value> = serde_json::from_str(json).map_err(invalid)?;
+    Ok(decks
+        .into_iter()
+        .filter_map(|(id, deck)| Some((id.parse().ok()?, deck["name"].as_str()?.to_owned())))
+        .collect())
+}
+
+fn load_cards(conn: &Connection) -> Result<Vec<AnkiCard>, AnkiError> {
+    let (sql, values) = Query::select()
+        .column((Cards::Table, Cards::Id))
+        .column((Cards::Table, Cards::Did))
+        .column((Cards::Table, Cards::Odid))
+        .column((Cards::Table, Cards::Ord))
+        .column((Cards::Table, Cards::Type))
+        .column((Cards::Table, Cards::Due))
+        .column((Cards::Table, Cards::Odue))
+        .column((Cards::Table, Cards::Ivl))
+        .column((Cards::Table, Cards::Factor))
+        .column((Cards::Table, Cards::Reps))
+        .column((Cards::Table, Cards::Lapses))
+        .column((Notes::Table, Notes::Guid))
+        .column((Notes::Table, Notes::Mid))
+        .column((Notes::Table, Notes::Flds))
+        .from(Cards::Table)
+        .inner_join(
+            Notes::Table,
+            Expr::tbl(Cards::Table, Cards::Nid).equals(Notes::Table, Notes::Id),
+        )
+        .order_by((Cards::Table, Cards::Nid), Order::Asc)
+        .order_by((Cards::Table, Cards::Ord), Order::Asc)
+        .build(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(sql.as_str())?;
+    let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+    let cards = rows
+        .map(|row| {
+            // Cards in a filtered deck keep their home deck and due date in
+            // the original columns.
+            let original_deck: i64 = row.get(2)?;
+            let original_due: i64 = row.get(6)?;
+            Ok(AnkiCard {
+                id: row.get(0)?,
+                deck: if original_deck != 0 {
+                    original_deck
+                } else {
+                    row.get(1)?
+                },
+                ord: row.get(3)?,
+                kind: row.get(4)?,
+                due: if original_deck != 0 {
+                    original_due
+                } else {
+                    row.get(5)?
+                },
+                interval: row.get(7)?,
+                factor: row.get(8)?,
+                reps: row.get(9)?,
+                lapses: row.get(10)?,
+                guid: row.get(11)?,
+                model: row.get(12)?,
+                fields: row.get(13)?,
+            })
+        })
+        .collect()?;
+    Ok(cards)
+}
+
+/// The ID, question and answer of the card. Cloze notes have a card for every
+/// deletion number, the other notes show the first field and answer with the
+/// rest, or the other way around for the reversed cards.
+fn card_text(card: &AnkiCard, model: &Model, fields: &[&str]) -> Option<(String, String, String)> {
+    let field = |idx: usize| fields.get(idx).map(|html| html_to_org(html));
+    if model.cloze {
+        let number = card.ord as u32 + 1;
+        let (question, mut answer) = cloze::expand_number(&field(0)?, number)?;
+        if let Some(extra) = field(1).filter(|extra| !extra.is_empty()) {
+            answer += "\n\n";
+            answer += &extra;
+        }
+        return Some((format!("{}-c{}", card.guid, number), question, answer));
+    }
+
+    let mut texts: Vec<String> = (0..fields.len())
+        .filter(|idx| model.fields.get(*idx).map(|name| name.as_str()) != Some(CONTEXT_FIELD))
+        .filter_map(field)
+        .filter(|text| !text.is_empty())
+        .collect();
+    if texts.is_empty() {
+        return None;
+    }
+    let uid = if card.ord == 0 {
+        card.guid.clone()
+    } else {
+        let last = texts.len() - 1;
+        texts.swap(0, last.min(1));
+        format!("{}-{}", card.guid, card.ord + 1)
+    };
+    let question = texts.remove(0);
+    Some((uid, question, texts.join("\n\n")))
+}
+
+/// The context of a deck. The first headline is tagged as the context, so the
+/// document view shows it.
+fn context_content(name: &str, context: Option<&str>) -> String {
+    match context.filter(|context| context.starts_with("* ")) {
+        Some(context) => {
+            let (title, rest) = context.split_once('\n').unwrap_or((context, ""));
+            let mut content = title.to_owned();
+            if !title.contains(":context:") {
+                content += " :context:";
+            }
+            content.push('\n');
+            if !rest.is_empty() {
+                content += rest;
+                content.push('\n');
+            }
+            content
+        }
+        None => match context.filter(|context| !context.is_empty()) {
+            Some(context) => format!("* {} :context:\n{}\n", name, context),
+            None => format!("* {} :context:\n", name),
+        },
+    }
+}
+
+/// Maps the scheduling of an Anki card onto the stats. Review cards are due
+/// in days since the creation of the collection, learning cards at a time in
+/// seconds.
+fn schedule(card: &AnkiCard, created: NaiveDate, today: NaiveDate) -> (Stats, Option<NaiveDate>) {
+    let mut stats = Stats::new();
+    if card.kind == 0 {
+        return (stats, None);
+    }
+    let scheduled = if card.due > 1_000_000_000 {
+        NaiveDateTime::from_timestamp(card.due, 0).date()
+    } else if card.kind == 2 {
+        created + Duration::days(card.due)
+    } else {
+        today
+    };
+    // The interval of a learning card is negative, in seconds.
+    stats.interval = card.interval.max(0);
+    if card.factor > 0 {
+        stats.difficultly = (card.factor as f64 / 1000.0).max(1.3);
+    }
+    if card.kind == 2 {
+        stats.num_reps = (card.reps - card.lapses).clamp(1, i16::MAX as i64) as i16;
+    }
+    stats.last_review = Some(scheduled - Duration::days(stats.interval));
+    (stats, Some(scheduled))
+}
+
+fn has_table(conn: &Connection, name: &str) -> Result<bool, AnkiError> {
+    let count: i64 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// The answers given to the imported cards. Manual reschedules are left out.
+fn load_reviews(conn: &Connection, card_ids: &HashMap<i64, i64>) -> Result<Vec<Review>, AnkiError> {
+    let (sql, values) = Query::select()
+        .columns(vec![
+            Revlog::Id,
+            Revlog::Cid,
+            Revlog::Ease,
+            Revlog::Ivl,
+            Revlog::LastIvl,
+            Revlog::Factor,
+            Revlog::Time,
+        ])
+        .from(Revlog::Table)
+        .and_where(Expr::col(Revlog::Ease).between(1, 4))
+        .order_by(Revlog::Id, Order::Asc)
+        .build(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(sql.as_str())?;
+    let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+    let reviews: Vec<Option<Review>> = rows
+        .map(|row| {
+            let card_id = match card_ids.get(&row.get::<_, i64>(1)?) {
+                Some(id) => *id,
+                None => return Ok(None),
+            };
+            let factor: i64 = row.get(5)?;
+            Ok(Some(Review {
+                card_id,
+                reviewed_at: Utc.timestamp_millis(row.get(0)?),
+                grade: grade(row.get(2)?),
+                previous_interval: row.get::<_, i64>(4)?.max(0),
+                new_interval: row.get::<_, i64>(3)?.max(0),
+                // Learning answers leave the factor at zero.
+                ease: if factor > 0 {
+                    factor as f64 / 1000.0
+                } else {
+                    2.5
+                },
+                duration: row.get(6)?,
+            }))
+        })
+        .collect()?;
+    Ok(reviews.into_iter().flatten().collect())
+}
+
+/// The answer buttons of Anki, from 1 (Again) to 4 (Easy).
+fn grade(button: i64) -> Grade {
+    match button {
+        1 => Grade::Again,
+        2 => Grade::Hard,
+        3 => Grade::Good,
+        _ => Grade::Easy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+    use crate::anki::export;
+
+    fn database() -> Database {
+        let mut conn = Connection::open_in_memory().unwrap();
+        Database::migrate(&mut conn, Path::new("test.db3")).unwrap();
+        Database { conn }
+    }
+
+    #[test]
+    fn export_and_import_again() {
+        let source = database();
+        let file_id = source.insert_file(Path::new("rust.org")).unwrap();
+        let doc_id = source
+            .insert_documents("* Rust :context:\nA systems language.\n", "Rust", file_id)
+            .unwrap();
+        let mut stats = Stats::new();
+        stats.interval = 10;
+        stats.num_reps = 3;
+        stats.difficultly = 2.6;
+        stats.last_review = Some(NaiveDate::from_ymd(2022, 4, 10));
+        let mut reviewed = FlashCard::from_db(
+            "What is Rust?",
+            "A /language/",
+            0,
+            Some("rust".to_owned()),
+            doc_id,
+            stats,
+        );
+        reviewed.set_scheduled(Some(NaiveDate::from_ymd(2022, 4, 20)));
+        let card_id = source.insert_flashcard(&reviewed).unwrap();
+        let new = FlashCard::from_db(
+            "Who made it?",
+            "Graydon Hoare",
+            0,
+            None,
+            doc_id,
+            Stats::new(),
+        );
+        source.insert_flashcard(&new).unwrap();
+        let reviewed_at = Utc.ymd(2022, 4, 10).and_hms(9, 0, 0);
+        source
+            .insert_reviews(&vec![Review {
+                card_id,
+                reviewed_at,
+                grade: Grade::Good,
+                previous_interval: 4,
+                new_interval: 10,
+                ease: 2.6,
+                duration: 5000,
+            }])
+            .unwrap();
+
+        let package = std::env::temp_dir().join(format!("flashcards-{}.apkg", process::id()));
+        let exported = export(&source, &package).unwrap();
+        assert_eq!(
+            (exported.decks, exported.cards, exported.reviews),
+            (1, 2, 1)
+        );
+
+        let target = database();
+        let imported = import_package(&target, &package).unwrap();
+        assert_eq!(
+            (
+                imported.decks,
+                imported.cards,
+                imported.reviews,
+                imported.skipped
+            ),
+            (1, 2, 1, 0)
+        );
+        let documents = target.load_documents().unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].get_title(), "Rust");
+        assert!(documents[0].get_contents().contains("A systems language."));
+
+        let cards = target.document_flashcards(documents[0].get_id()).unwrap();
+        let card = cards.iter().find(|c| c.get_uid() == Some("rust")).unwrap();
+        assert_eq!(card.get_questions(), "What is Rust?");
+        assert_eq!(card.get_answers(), "A /language/");
+        assert_eq!(card.get_scheduled(), Some(NaiveDate::from_ymd(2022, 4, 20)));
+        assert_eq!(card.get_stats().interval, 10);
+        assert_eq!(card.get_stats().num_reps, 3);
+        assert_eq!(card.get_stats().difficultly, 2.6);
+        assert_eq!(
+            card.get_stats().last_review,
+            Some(NaiveDate::from_ymd(2022, 4, 10))
+        );
+        let new = cards
+            .iter()
+            .find(|c| c.get_questions() == "Who made it?")
+            .unwrap();
+        // A new card is due on the day it is imported.
+        assert_eq!(new.get_scheduled(), Some(Utc::now().date().naive_local()));
+        assert_eq!(new.get_stats().last_review, None);
+        assert!(new.get_uid().unwrap().starts_with("flashcards-"));
+
+        let reviews = target.load_reviews().unwrap();
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].card_id, card.get_id());
+        assert_eq!(reviews[0].grade, Grade::Good);
+        assert_eq!(reviews[0].reviewed_at.hour(), 9);
+        assert_eq!(
+            (reviews[0].previous_interval, reviews[0].new_interval),
+            (4, 10)
+        );
+
+        // The cards keep their IDs, so a second import adds nothing.
+        let again = import_package(&target, &package).unwrap();
+        assert_eq!((again.cards, again.skipped), (0, 2));
+        fs::remove_file(&package).unwrap();
+    }
+
+    #[test]
+    fn cloze_and_reversed_cards() {
+        let card = |ord, guid: &str| AnkiCard {
+            id: 1,
+            deck: 1,
+            ord,
+            kind: 0,
+            due: 0,
+            interval: 0,
+            factor: 0,
+            reps: 0,
+            lapses: 0,
+            guid: guid.to_owned(),
+            model: 1,
+            fields: String::new(),
+        };
+        let cloze = Model {
+            cloze: true,
+            fields: vec!["Text".to_owned(), "Extra".to_owned()],
+        };
+        let fields = [
+            "{{c1::Paris}} is the capital of {{c2::France}}",
+            "<b>Europe</b>",
+        ];
+        let (uid, question, answer) = card_text(&card(1, "geo"), &cloze, &fields).unwrap();
+        assert_eq!(uid, "geo-c2");
+        assert!(question.contains("Paris"));
+        assert!(!question.contains("France"));
+        assert!(answer.starts_with("France"));
+        assert!(answer.ends_with("*Europe*"));
+
+        let reversed = Model {
+            cloze: false,
+            fields: vec!["Front".to_owned(), "Back".to_owned(), "Context".to_owned()],
+        };
+        let fields = ["chat", "cat", "<p>French</p>"];
+        let text = card_text(&card(0, "pet"), &reversed, &fields).unwrap();
+        assert_eq!(
+            text,
+            ("pet".to_owned(), "chat".to_owned(), "cat".to_owned())
+        );
+        let text = card_text(&card(1, "pet"), &reversed, &fields).unwrap();
+        assert_eq!(
+            text,
+            ("pet-2".to_owned(), "cat".to_owned(), "chat".to_owned())
+        );
+    }
+}
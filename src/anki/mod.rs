@@ -0,0 +1,262 @@
+//! Exchanges decks with Anki. A package is a zip that holds the collection,
+//! an SQLite database, and the media files.
+
+mod export;
+mod import;
+mod schema;
+
+use std::{fmt, io};
+
+use orgize::Org;
+
+pub use export::export;
+pub use import::import_package;
+
+/// Name of the collection inside a package. This legacy name is read by every
+/// Anki version.
+const COLLECTION: &str = "collection.anki2";
+
+/// Anki separates the fields of a note with the unit separator.
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+#[derive(Debug)]
+pub enum AnkiError {
+    Io(io::Error),
+    Database(rusqlite::Error),
+    Zip(zip::result::ZipError),
+    /// The package holds no collection this version can read.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for AnkiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnkiError::Io(err) => write!(f, "cannot access the package: {}", err),
+            AnkiError::Database(err) => write!(f, "cannot read the collection: {}", err),
+            AnkiError::Zip(err) => write!(f, "the package is not a valid zip: {}", err),
+            AnkiError::Unsupported(reason) => write!(f, "unsupported package: {}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for AnkiError {
+    fn from(err: io::Error) -> Self {
+        AnkiError::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for AnkiError {
+    fn from(err: rusqlite::Error) -> Self {
+        AnkiError::Database(err)
+    }
+}
+
+impl From<zip::result::ZipError> for AnkiError {
+    fn from(err: zip::result::ZipError) -> Self {
+        AnkiError::Zip(err)
+    }
+}
+
+/// Renders org text as the HTML of a field.
+fn org_to_html(text: &str) -> String {
+    let mut html: Vec<u8> = vec![];
+    if Org::parse(text).write_html(&mut html).is_err() {
+        return String::new();
+    }
+    let html = String::from_utf8_lossy(&html);
+    // The document and section wrappers mean nothing inside a field.
+    ["<main>", "</main>", "<section>", "</section>"]
+        .iter()
+        .fold(html.into_owned(), |html, tag| html.replace(tag, ""))
+}
+
+/// Converts the HTML of a field into org text. Line breaks, lists, headings,
+/// emphasis, links and images are kept, every other tag is dropped.
+fn html_to_org(html: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<bool> = vec![];
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text += &decode_entities(&rest[..start]);
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "br" => text.push('\n'),
+            "div" | "p" | "ul" | "ol" | "tr" | "pre" | "blockquote" | "hr" => new_line(&mut text),
+            "li" if !closing => {
+                new_line(&mut text);
+                text += "- ";
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                new_line(&mut text);
+                if !closing {
+                    let level = name[1..].parse().unwrap_or(1);
+                    text += &"*".repeat(level);
+                    text.push(' ');
+                }
+            }
+            "b" | "strong" => text.push('*'),
+            "i" | "em" => text.push('/'),
+            "u" => text.push('_'),
+            "code" => text.push('~'),
+            "s" | "del" | "strike" => text.push('+'),
+            "a" if closing => {
+                // The closing tag of an anchor without a href has no link to end.
+                let opened = links.pop().unwrap_or(false);
+                if opened {
+                    text += "]]";
+                }
+            }
+            "a" => {
+                let href = attribute(tag, "href");
+                if let Some(href) = &href {
+                    text += &format!("[[{}][", href);
+                }
+                links.push(href.is_some());
+            }
+            "img" => {
+                if let Some(src) = attribute(tag, "src") {
+                    text += &format!("[[file:{}]]", src);
+                }
+            }
+            "script" | "style" if !closing => {
+                // Their contents are not text.
+                let close = format!("</{}", name);
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(idx) => &rest[idx..],
+                    None => "",
+                };
+            }
+            _ => {}
+        }
+    }
+    text += &decode_entities(rest);
+
+    // Blocks leave blank lines behind, at most one is kept in a row.
+    let mut org = String::new();
+    let mut blank = false;
+    for line in text.lines().map(|line| line.trim_end()) {
+        if line.is_empty() {
+            blank = !org.is_empty();
+            continue;
+        }
+        if blank {
+            org.push('\n');
+            blank = false;
+        }
+        org += line;
+        org.push('\n');
+    }
+    org.trim_end().to_owned()
+}
+
+fn new_line(text: &mut String) {
+    // The paragraph of a list item stays on the line of its bullet.
+    let bullet = text.ends_with("\n- ") || text == "- ";
+    if !text.is_empty() && !text.ends_with('\n') && !bullet {
+        text.push('\n');
+    }
+}
+
+/// The value of an attribute of a tag, quoted with double or single quotes.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    for quote in ['"', '\''] {
+        let pattern = format!("{}={}", name, quote);
+        if let Some(start) = lower.find(&pattern) {
+            let value = &tag[start + pattern.len()..];
+            let end = value.find(quote)?;
+            return Some(decode_entities(&value[..end]));
+        }
+    }
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded += &rest[..start];
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| Some((entity(&rest[1..end])?, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded + rest
+}
+
+fn entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let code = match code.strip_prefix(|c| c == 'x' || c == 'X') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_html_to_org() {
+        assert_eq!(
+            html_to_org(
+                "<div>A <b>bold</b> and <i>slanted</i> word</div><div>next&nbsp;line</div>"
+            ),
+            "A *bold* and /slanted/ word\nnext line"
+        );
+        assert_eq!(
+            html_to_org("<ul><li>one</li><li><a href=\"https://example.com\">two</a></li></ul>"),
+            "- one\n- [[https://example.com][two]]"
+        );
+        assert_eq!(
+            html_to_org("<h2>Title</h2><p>x &lt; y</p><script>alert(1)</script><img src='a.png'>"),
+            "** Title\nx < y\n[[file:a.png]]"
+        );
+        assert_eq!(html_to_org("&#65;&#x42;&unknown; &amp"), "AB&unknown; &amp");
+    }
+
+    #[test]
+    fn convert_org_to_html_and_back() {
+        let html = org_to_html("A *bold* and /slanted/ word");
+        assert!(!html.contains("<main>"));
+        assert_eq!(html_to_org(&html), "A *bold* and /slanted/ word");
+    }
+}
@@ -0,0 +1,305 @@
+//! The tables of an Anki collection, in the legacy format that every Anki
+//! version can import.
+
+use rusqlite::{Connection, Result};
+use sea_query::{ColumnDef, Iden, Index, SqliteQueryBuilder, Table};
+
+pub enum Col {
+    Table,
+    Id,
+    Crt,
+    Mod,
+    Scm,
+    Ver,
+    Dty,
+    Usn,
+    Ls,
+    Conf,
+    Models,
+    Decks,
+    Dconf,
+    Tags,
+}
+
+impl Iden for Col {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                Col::Table => "col",
+                Col::Id => "id",
+                Col::Crt => "crt",
+                Col::Mod => "mod",
+                Col::Scm => "scm",
+                Col::Ver => "ver",
+                Col::Dty => "dty",
+                Col::Usn => "usn",
+                Col::Ls => "ls",
+                Col::Conf => "conf",
+                Col::Models => "models",
+                Col::Decks => "decks",
+                Col::Dconf => "dconf",
+                Col::Tags => "tags",
+            }
+        )
+        .unwrap();
+    }
+}
+
+pub enum Notes {
+    Table,
+    Id,
+    Guid,
+    Mid,
+    Mod,
+    Usn,
+    Tags,
+    Flds,
+    Sfld,
+    Csum,
+    Flags,
+    Data,
+}
+
+impl Iden for Notes {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                Notes::Table => "notes",
+                Notes::Id => "id",
+                Notes::Guid => "guid",
+                Notes::Mid => "mid",
+                Notes::Mod => "mod",
+                Notes::Usn => "usn",
+                Notes::Tags => "tags",
+                Notes::Flds => "flds",
+                Notes::Sfld => "sfld",
+                Notes::Csum => "csum",
+                Notes::Flags => "flags",
+                Notes::Data => "data",
+            }
+        )
+        .unwrap();
+    }
+}
+
+pub enum Cards {
+    Table,
+    Id,
+    Nid,
+    Did,
+    Ord,
+    Mod,
+    Usn,
+    Type,
+    Queue,
+    Due,
+    Ivl,
+    Factor,
+    Reps,
+    Lapses,
+    Left,
+    Odue,
+    Odid,
+    Flags,
+    Data,
+}
+
+impl Iden for Cards {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                Cards::Table => "cards",
+                Cards::Id => "id",
+                Cards::Nid => "nid",
+                Cards::Did => "did",
+                Cards::Ord => "ord",
+                Cards::Mod => "mod",
+                Cards::Usn => "usn",
+                Cards::Type => "type",
+                Cards::Queue => "queue",
+                Cards::Due => "due",
+                Cards::Ivl => "ivl",
+                Cards::Factor => "factor",
+                Cards::Reps => "reps",
+                Cards::Lapses => "lapses",
+                Cards::Left => "left",
+                Cards::Odue => "odue",
+                Cards::Odid => "odid",
+                Cards::Flags => "flags",
+                Cards::Data => "data",
+            }
+        )
+        .unwrap();
+    }
+}
+
+pub enum Revlog {
+    Table,
+    Id,
+    Cid,
+    Usn,
+    Ease,
+    Ivl,
+    LastIvl,
+    Factor,
+    Time,
+    Type,
+}
+
+impl Iden for Revlog {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                Revlog::Table => "revlog",
+                Revlog::Id => "id",
+                Revlog::Cid => "cid",
+                Revlog::Usn => "usn",
+                Revlog::Ease => "ease",
+                Revlog::Ivl => "ivl",
+                Revlog::LastIvl => "lastIvl",
+                Revlog::Factor => "factor",
+                Revlog::Time => "time",
+                Revlog::Type => "type",
+            }
+        )
+        .unwrap();
+    }
+}
+
+pub enum Graves {
+    Table,
+    Usn,
+    Oid,
+    Type,
+}
+
+impl Iden for Graves {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                Graves::Table => "graves",
+                Graves::Usn => "usn",
+                Graves::Oid => "oid",
+                Graves::Type => "type",
+            }
+        )
+        .unwrap();
+    }
+}
+
+/// Every column of the collection is a required integer or text.
+fn integer<T: Iden + 'static>(column: T) -> ColumnDef {
+    ColumnDef::new(column).integer().not_null().to_owned()
+}
+
+fn text<T: Iden + 'static>(column: T) -> ColumnDef {
+    ColumnDef::new(column).text().not_null().to_owned()
+}
+
+pub fn create_tables(conn: &Connection) -> Result<()> {
+    let queries = [
+        Table::create()
+            .table(Col::Table)
+            .col(integer(Col::Id).primary_key())
+            .col(&mut integer(Col::Crt))
+            .col(&mut integer(Col::Mod))
+            .col(&mut integer(Col::Scm))
+            .col(&mut integer(Col::Ver))
+            .col(&mut integer(Col::Dty))
+            .col(&mut integer(Col::Usn))
+            .col(&mut integer(Col::Ls))
+            .col(&mut text(Col::Conf))
+            .col(&mut text(Col::Models))
+            .col(&mut text(Col::Decks))
+            .col(&mut text(Col::Dconf))
+            .col(&mut text(Col::Tags))
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(Notes::Table)
+            .col(integer(Notes::Id).primary_key())
+            .col(&mut text(Notes::Guid))
+            .col(&mut integer(Notes::Mid))
+            .col(&mut integer(Notes::Mod))
+            .col(&mut integer(Notes::Usn))
+            .col(&mut text(Notes::Tags))
+            .col(&mut text(Notes::Flds))
+            .col(&mut integer(Notes::Sfld))
+            .col(&mut integer(Notes::Csum))
+            .col(&mut integer(Notes::Flags))
+            .col(&mut text(Notes::Data))
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(Cards::Table)
+            .col(integer(Cards::Id).primary_key())
+            .col(&mut integer(Cards::Nid))
+            .col(&mut integer(Cards::Did))
+            .col(&mut integer(Cards::Ord))
+            .col(&mut integer(Cards::Mod))
+            .col(&mut integer(Cards::Usn))
+            .col(&mut integer(Cards::Type))
+            .col(&mut integer(Cards::Queue))
+            .col(&mut integer(Cards::Due))
+            .col(&mut integer(Cards::Ivl))
+            .col(&mut integer(Cards::Factor))
+            .col(&mut integer(Cards::Reps))
+            .col(&mut integer(Cards::Lapses))
+            .col(&mut integer(Cards::Left))
+            .col(&mut integer(Cards::Odue))
+            .col(&mut integer(Cards::Odid))
+            .col(&mut integer(Cards::Flags))
+            .col(&mut text(Cards::Data))
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(Revlog::Table)
+            .col(integer(Revlog::Id).primary_key())
+            .col(&mut integer(Revlog::Cid))
+            .col(&mut integer(Revlog::Usn))
+            .col(&mut integer(Revlog::Ease))
+            .col(&mut integer(Revlog::Ivl))
+            .col(&mut integer(Revlog::LastIvl))
+            .col(&mut integer(Revlog::Factor))
+            .col(&mut integer(Revlog::Time))
+            .col(&mut integer(Revlog::Type))
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(Graves::Table)
+            .col(&mut integer(Graves::Usn))
+            .col(&mut integer(Graves::Oid))
+            .col(&mut integer(Graves::Type))
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("ix_cards_nid")
+            .table(Cards::Table)
+            .col(Cards::Nid)
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("ix_cards_sched")
+            .table(Cards::Table)
+            .col(Cards::Did)
+            .col(Cards::Queue)
+            .col(Cards::Due)
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("ix_revlog_cid")
+            .table(Revlog::Table)
+            .col(Revlog::Cid)
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("ix_notes_csum")
+            .table(Notes::Table)
+            .col(Notes::Csum)
+            .build(SqliteQueryBuilder),
+    ]
+    .join(";");
+    conn.execute_batch(&queries)
+}
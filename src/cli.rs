@@ -0,0 +1,470 @@
+//! The command line interface, to import, check and review the cards without
+//! opening the window.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use rand::prelude::*;
+use uuid::Uuid;
+
+use crate::{
+    anki, csv,
+    database::Database,
+    import,
+    profile::{self, Location},
+    server,
+    session::Session,
+    sr::{Algorithm, Grade, Scheduler, FSRS_WEIGHTS},
+    tui,
+};
+
+const USAGE: &str = "Usage: flashcards [--profile <name>] [--database <path>] [<command> [<args>]]
+
+Without a command the window is opened.
+
+Options:
+  --profile <name>     Use the database of the profile (default: FLASHCARDS_PROFILE,
+                       or default)
+  --database <path>    Use the database at the path (default: FLASHCARDS_DB, or the
+                       file of the profile in the data directory)
+
+Commands:
+  import <path>...     Import folders, org, markdown, CSV/TSV files or Anki packages
+      --include <globs>    Files to import from folders (default: *.org, *.md)
+      --exclude <globs>    Files and folders to skip
+      --follow-links       Follow symbolic links
+      --write-ids          Write card IDs into org files
+  due                  Count the due cards of every document
+      --total              Print the total only
+  stats                Show counts over the whole collection
+  export <path>        Export to an .apkg, .csv or .tsv file
+      --document <title>   Export a single document to CSV or TSV
+  review               Review the due cards in the terminal
+      --limit <n>          Review at most n cards (default: 50)
+      --algorithm <name>   sm2 or fsrs (default: the one of the profile)
+  tui                  Review the due cards in a full screen terminal view
+      --limit <n>          Review at most n cards (default: 50)
+      --algorithm <name>   sm2 or fsrs (default: the one of the profile)
+  serve                Answer the JSON API for editors on a local address
+      --bind <address>     The address to listen on (default: 127.0.0.1:7878,
+                           or FLASHCARDS_BIND)
+      --token <token>      The token of the requests (default: FLASHCARDS_TOKEN,
+                           or a new one that is printed)
+      --include <globs>    Files to import from folders (default: *.org, *.md)
+      --exclude <globs>    Files and folders to skip
+      --algorithm <name>   sm2 or fsrs (default: the one of the profile)
+  profiles             List the profiles and their databases
+  help                 Show this message";
+
+/// Options that take a value. Every other option is a switch.
+const VALUE_OPTIONS: [&str; 7] = [
+    "include",
+    "exclude",
+    "document",
+    "limit",
+    "algorithm",
+    "bind",
+    "token",
+];
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    switches: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = Args {
+            positional: vec![],
+            options: HashMap::new(),
+            switches: vec![],
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if VALUE_OPTIONS.contains(&name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("--{} needs a value", name))?;
+                    parsed.options.insert(name.to_owned(), value.clone());
+                }
+                Some(name) => parsed.switches.push(name.to_owned()),
+                None => parsed.positional.push(arg.clone()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.iter().any(|switch| switch == name)
+    }
+
+    /// Fails on a switch the command doesn't know.
+    fn check_switches(&self, known: &[&str]) -> Result<(), String> {
+        match self.switches.iter().find(|s| !known.contains(&s.as_str())) {
+            Some(switch) => Err(format!("unknown option --{}", switch)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Takes the options that come before the command, which select the
+/// database of the window as well.
+pub fn take_location(args: &mut Vec<String>) -> Result<Location, String> {
+    let mut database = None;
+    let mut profile = None;
+    while let Some(option) = args.first().filter(|arg| arg.starts_with("--")) {
+        let slot = match option.as_str() {
+            "--database" => &mut database,
+            "--profile" => &mut profile,
+            _ => break,
+        };
+        let option = args.remove(0);
+        if args.is_empty() {
+            return Err(format!("{} needs a value", option));
+        }
+        *slot = Some(args.remove(0));
+    }
+    Location::resolve(database.as_deref(), profile.as_deref())
+}
+
+/// Runs the command and returns the exit code.
+pub fn run(args: &[String], location: &Location) -> i32 {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => ("help", args),
+    };
+    if matches!(command, "help" | "--help" | "-h") {
+        println!("{}", USAGE);
+        return 0;
+    }
+
+    let result = Args::parse(rest).and_then(|args| {
+        if command == "profiles" {
+            return profiles(location);
+        }
+        let mut db = Database::connect(&location.database).map_err(|err| err.to_string())?;
+        match command {
+            "import" => import(&db, &args),
+            "due" => due(&db, &args),
+            "stats" => stats(&db, &args),
+            "export" => export(&db, &args),
+            "review" => review(&mut db, &args),
+            "tui" => review_screen(&mut db, &args),
+            "serve" => serve(&mut db, &args),
+            _ => Err(format!("unknown command {}\n\n{}", command, USAGE)),
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
+        }
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Snapshots the database before an import and after a review, like the
+/// window does, keeping as many snapshots as set in the window.
+fn back_up(db: &Database) -> Result<(), String> {
+    db.back_up(db.backup_count())
+        .map(|_| ())
+        .map_err(|err| format!("cannot back up the database: {}", err))
+}
+
+fn import(db: &Database, args: &Args) -> Result<(), String> {
+    args.check_switches(&["follow-links", "write-ids"])?;
+    if args.positional.is_empty() {
+        return Err(String::from("import needs a path"));
+    }
+    let write_ids = args.switch("write-ids");
+    back_up(db)?;
+    let mut failed = 0;
+    for root in args.positional.iter().map(Path::new) {
+        let paths = if root.is_dir() {
+            import::find_files(
+                root,
+                args.option("include").unwrap_or("*.org, *.md"),
+                args.option("exclude").unwrap_or(""),
+                args.switch("follow-links"),
+            )
+        } else {
+            vec![root.to_path_buf()]
+        };
+        let mut unchanged = 0;
+        for path in paths {
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            let name = if name.is_empty() {
+                root.display().to_string()
+            } else {
+                name
+            };
+            match import::import_file(db, &path, write_ids) {
+                Ok(Some(summary)) => println!("{}: {}", name, summary),
+                Ok(None) => unchanged += 1,
+                Err(err) => {
+                    eprintln!("{}: {}", name, err);
+                    failed += 1;
+                }
+            }
+        }
+        if unchanged > 0 {
+            println!("{} unchanged files skipped", unchanged);
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} files could not be imported", failed));
+    }
+    Ok(())
+}
+
+fn profiles(location: &Location) -> Result<(), String> {
+    let mut profiles = profile::profiles();
+    if !profiles.contains(&location.profile) {
+        profiles.push(location.profile.clone());
+    }
+    for name in profiles {
+        let (marker, database) = if name == location.profile {
+            ("*", location.database.clone())
+        } else {
+            (" ", profile::database_of(&name))
+        };
+        println!("{} {:<16} {}", marker, name, database.display());
+    }
+    Ok(())
+}
+
+fn due(db: &Database, args: &Args) -> Result<(), String> {
+    args.check_switches(&["total"])?;
+    let counts = db.due_counts().map_err(|err| err.to_string())?;
+    let total: i64 = counts.iter().map(|(_, count)| count).sum();
+    if args.switch("total") {
+        println!("{}", total);
+        return Ok(());
+    }
+    for (title, count) in &counts {
+        println!("{:>5}  {}", count, title);
+    }
+    println!("{:>5}  due in total", total);
+    Ok(())
+}
+
+fn stats(db: &Database, args: &Args) -> Result<(), String> {
+    args.check_switches(&[])?;
+    let stats = db.collection_stats().map_err(|err| err.to_string())?;
+    println!("Documents:      {}", stats.documents);
+    println!("Cards:          {}", stats.cards);
+    println!("New cards:      {}", stats.new_cards);
+    println!("Due cards:      {}", stats.due);
+    println!("Retired cards:  {}", stats.retired);
+    println!("Reviews:        {}", stats.reviews);
+    println!("Reviews today:  {}", stats.reviews_today);
+    if let Some(retention) = stats.retention {
+        println!("Retention (30 days): {:.1}%", retention * 100.0);
+    }
+    Ok(())
+}
+
+fn export(db: &Database, args: &Args) -> Result<(), String> {
+    args.check_switches(&[])?;
+    let path = match args.positional.as_slice() {
+        [path] => Path::new(path),
+        _ => return Err(String::from("export needs a single path")),
+    };
+    let document = match args.option("document") {
+        Some(title) => match db.find_titled_document(title) {
+            Ok(Some(id)) => Some(id),
+            Ok(None) => return Err(format!("there is no document titled {}", title)),
+            Err(err) => return Err(err.to_string()),
+        },
+        None => None,
+    };
+    match extension(path).as_str() {
+        "apkg" if document.is_some() => Err(String::from(
+            "--document only applies to CSV and TSV exports",
+        )),
+        "apkg" => anki::export(db, path)
+            .map(|summary| println!("Exported {}", summary))
+            .map_err(|err| err.to_string()),
+        "csv" | "tsv" => csv::export(db, document, path)
+            .map(|count| println!("Exported {} cards", count))
+            .map_err(|err| err.to_string()),
+        _ => Err(String::from("the path must end with .apkg, .csv or .tsv")),
+    }
+}
+
+/// Prints the prompt and reads a line. Returns `None` at the end of the
+/// input.
+fn read(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_lowercase()),
+    }
+}
+
+fn print_context(db: &Database, doc_id: i64) {
+    match db.load_data(doc_id) {
+        Ok(documents) => {
+            for document in documents {
+                println!("\n{}", document.get_contents().trim_end());
+            }
+        }
+        Err(err) => println!("Error: {:?}", err),
+    }
+}
+
+/// The scheduler and the FSRS weights picked in the window for the profile,
+/// so the cards are not scheduled by two algorithms. `--algorithm` overrides
+/// the scheduler.
+fn scheduler(db: &Database, args: &Args) -> Result<Box<dyn Scheduler>, String> {
+    let algorithm = match args.option("algorithm") {
+        Some(name) => {
+            Algorithm::from_name(name).ok_or_else(|| format!("unknown algorithm {}", name))?
+        }
+        None => db
+            .algorithm()
+            .map_err(|err| err.to_string())?
+            .unwrap_or_default(),
+    };
+    let weights = db
+        .fsrs_weights()
+        .map_err(|err| err.to_string())?
+        .unwrap_or(FSRS_WEIGHTS);
+    Ok(algorithm.scheduler(&weights))
+}
+
+/// The number of cards and the scheduler of a review.
+fn review_options(db: &Database, args: &Args) -> Result<(i32, Box<dyn Scheduler>), String> {
+    args.check_switches(&[])?;
+    let limit = match args.option("limit") {
+        Some(limit) => limit
+            .parse::<u32>()
+            .ok()
+            .filter(|limit| *limit > 0)
+            .and_then(|limit| i32::try_from(limit).ok())
+            .ok_or_else(|| format!("{} is not a number of cards", limit))?,
+        None => 50,
+    };
+    Ok((limit, scheduler(db, args)?))
+}
+
+fn review_screen(db: &mut Database, args: &Args) -> Result<(), String> {
+    let (limit, scheduler) = review_options(db, args)?;
+    match tui::run(db, limit, scheduler.as_ref()) {
+        Ok(0) => println!("No cards were reviewed."),
+        Ok(reviewed) => {
+            println!("{} cards reviewed.", reviewed);
+            back_up(db)?;
+        }
+        Err(err) => return Err(err.to_string()),
+    }
+    Ok(())
+}
+
+fn review(db: &mut Database, args: &Args) -> Result<(), String> {
+    let (limit, scheduler) = review_options(db, args)?;
+    let mut rng = thread_rng();
+
+    let mut session = Session::new();
+    session.fetch(db, limit, &[]);
+    if session.len() == 0 {
+        println!("No cards are due.");
+        return Ok(());
+    }
+    println!("{} cards are due.", session.len());
+
+    'session: while !session.is_done() {
+        let card = session.card();
+        println!("\n{}", card.get_questions());
+        loop {
+            match read("[Enter] answer  [c] context  [q] quit: ").as_deref() {
+                None | Some("q") => break 'session,
+                Some("c") => print_context(db, card.get_doc_id()),
+                Some(_) => break,
+            }
+        }
+
+        println!("\n{}\n", card.get_answers());
+        let labels: Vec<String> = Grade::ALL
+            .iter()
+            .enumerate()
+            .map(|(idx, grade)| format!("[{}] {}", idx + 1, grade.label()))
+            .collect();
+        let prompt = format!("{}  [c] context  [q] quit: ", labels.join("  "));
+        let grade = loop {
+            match read(&prompt).as_deref() {
+                None | Some("q") => break 'session,
+                Some("c") => print_context(db, card.get_doc_id()),
+                Some(answer) => {
+                    let grade = answer
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|number| Grade::ALL.get(number.wrapping_sub(1)));
+                    if let Some(grade) = grade {
+                        break *grade;
+                    }
+                }
+            }
+        };
+        session.update_and_next(grade, scheduler.as_ref(), &mut rng);
+    }
+
+    let reviewed = session.reviewed_cards().len();
+    session.save_to_database(db);
+    println!("\n{} cards reviewed.", reviewed);
+    if reviewed > 0 {
+        back_up(db)?;
+    }
+    Ok(())
+}
+
+fn serve(db: &mut Database, args: &Args) -> Result<(), String> {
+    args.check_switches(&[])?;
+    let bind = match args.option("bind") {
+        Some(bind) => bind.to_owned(),
+        None => env::var("FLASHCARDS_BIND").unwrap_or_else(|_| server::DEFAULT_BIND.to_owned()),
+    };
+    let token = match args.option("token") {
+        // An empty token would let any request in.
+        Some("") => return Err(String::from("the token must not be empty")),
+        Some(token) => token.to_owned(),
+        None => match env::var("FLASHCARDS_TOKEN") {
+            Ok(token) if !token.is_empty() => token,
+            _ => {
+                let token = Uuid::new_v4().to_simple().to_string();
+                println!("Token: {}", token);
+                token
+            }
+        },
+    };
+    let options = server::Options {
+        bind,
+        token,
+        scheduler: scheduler(db, args)?,
+        include: args.option("include").unwrap_or("*.org, *.md").to_owned(),
+        exclude: args.option("exclude").unwrap_or("").to_owned(),
+    };
+    server::serve(db, &options)
+}
@@ -0,0 +1,269 @@
+//! Cloze deletions. A deletion is written as `{{c1::text}}` or
+//! `{{c1::text::hint}}`, and can hold other deletions. Every deletion number
+//! becomes a card, that shows the sentence with the deletions of that number
+//! blanked and the others filled in.
+//!
+//! The org native way of writing a cloze is a sentence with `[...]` gaps, and
+//! an answer headline for each of the gaps.
+
+const GAP: &str = "[...]";
+
+enum Piece<'a> {
+    Text(&'a str),
+    Deletion {
+        number: u32,
+        text: &'a str,
+        hint: Option<&'a str>,
+    },
+}
+
+/// Splits the text into plain text and deletions. A deletion can hold other
+/// deletions, which are split when the deletion is blanked or filled in.
+fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("{{c") {
+        let inner = &rest[start + 3..];
+        let deletion = closing(inner).and_then(|end| {
+            let (number, body) = inner[..end].split_once("::")?;
+            let number = number.parse::<u32>().ok()?;
+            Some((end, number, body))
+        });
+        match deletion {
+            Some((end, number, body)) => {
+                pieces.push(Piece::Text(&rest[..start]));
+                let (text, hint) = split_hint(body);
+                pieces.push(Piece::Deletion { number, text, hint });
+                rest = &inner[end + 2..];
+            }
+            None => {
+                // Not a deletion, keep the braces as they are.
+                pieces.push(Piece::Text(&rest[..start + 3]));
+                rest = inner;
+            }
+        }
+    }
+    pieces.push(Piece::Text(rest));
+    pieces
+}
+
+/// Finds the `}}` that closes a deletion, skipping the deletions within it.
+fn closing(inner: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut idx = 0;
+    while idx < inner.len() {
+        let rest = &inner[idx..];
+        if rest.starts_with("{{") {
+            depth += 1;
+            idx += 2;
+        } else if rest.starts_with("}}") {
+            if depth == 0 {
+                return Some(idx);
+            }
+            depth -= 1;
+            idx += 2;
+        } else {
+            idx += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+/// Splits the hint off the text of a deletion. The `::` of the deletions
+/// within the text are not a hint.
+fn split_hint(body: &str) -> (&str, Option<&str>) {
+    let mut depth = 0;
+    let mut idx = 0;
+    while idx < body.len() {
+        let rest = &body[idx..];
+        if rest.starts_with("{{") {
+            depth += 1;
+            idx += 2;
+        } else if rest.starts_with("}}") {
+            depth -= 1;
+            idx += 2;
+        } else if depth == 0 && rest.starts_with("::") {
+            return (&body[..idx], Some(&body[idx + 2..]));
+        } else {
+            idx += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    (body, None)
+}
+
+/// Checks if the text contains `{{cN::...}}` deletions.
+pub fn has_cloze(text: &str) -> bool {
+    pieces(text)
+        .iter()
+        .any(|piece| matches!(piece, Piece::Deletion { .. }))
+}
+
+/// Collects the deletion numbers, including those of nested deletions.
+fn numbers(pieces: &[Piece<'_>], found: &mut Vec<u32>) {
+    for piece in pieces {
+        if let Piece::Deletion { number, text, .. } = piece {
+            found.push(*number);
+            numbers(&self::pieces(text), found);
+        }
+    }
+}
+
+/// Blanks the deletions with the `active` number and fills in the others.
+/// Without an active number every deletion is filled in.
+fn blank_into(
+    pieces: &[Piece<'_>],
+    active: Option<u32>,
+    question: &mut String,
+    answers: &mut Vec<String>,
+) {
+    for piece in pieces.iter() {
+        match piece {
+            Piece::Text(text) => *question += text,
+            Piece::Deletion { number, text, hint } if Some(*number) == active => {
+                match hint {
+                    Some(hint) => {
+                        *question += "[";
+                        *question += hint;
+                        *question += "]";
+                    }
+                    None => *question += GAP,
+                }
+                let mut answer = String::new();
+                blank_into(&self::pieces(text), None, &mut answer, &mut vec![]);
+                answers.push(answer);
+            }
+            Piece::Deletion { text, .. } => {
+                blank_into(&self::pieces(text), active, question, answers)
+            }
+        }
+    }
+}
+
+fn blank(pieces: &[Piece<'_>], active: u32) -> (String, String) {
+    let mut question = String::new();
+    let mut answers = vec![];
+    blank_into(pieces, Some(active), &mut question, &mut answers);
+    (question, answers.join(", "))
+}
+
+/// Returns the number, question and answer of every deletion number in the
+/// text, ordered by number. The numbers can have gaps, like `c1` and `c3`.
+pub fn expand(text: &str) -> Vec<(u32, String, String)> {
+    let pieces = pieces(text);
+    let mut found = vec![];
+    numbers(&pieces, &mut found);
+    found.sort_unstable();
+    found.dedup();
+
+    found
+        .iter()
+        .map(|active| {
+            let (question, answer) = blank(&pieces, *active);
+            (*active, question, answer)
+        })
+        .collect()
+}
+
+/// Returns the question and answer of a single deletion number, if the text
+/// has deletions with that number.
+pub fn expand_number(text: &str, number: u32) -> Option<(String, String)> {
+    let pieces = pieces(text);
+    let mut found = vec![];
+    numbers(&pieces, &mut found);
+    if found.contains(&number) {
+        Some(blank(&pieces, number))
+    } else {
+        None
+    }
+}
+
+/// Returns a question and answer for every `[...]` gap in the text, numbered
+/// from 1 like the deletions. The answers fill the gaps in order, so they must
+/// match the number of gaps.
+pub fn expand_gaps(text: &str, answers: &[String]) -> Vec<(u32, String, String)> {
+    let parts: Vec<&str> = text.split(GAP).collect();
+    if parts.len() < 2 || parts.len() - 1 != answers.len() {
+        return vec![];
+    }
+
+    (0..answers.len())
+        .map(|active| {
+            let mut question = String::from(parts[0]);
+            for (idx, part) in parts[1..].iter().enumerate() {
+                if idx == active {
+                    question += GAP;
+                } else {
+                    question += &answers[idx];
+                }
+                question += part;
+            }
+            (active as u32 + 1, question, answers[active].clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(number: u32, question: &str, answer: &str) -> (u32, String, String) {
+        (number, question.to_owned(), answer.to_owned())
+    }
+
+    #[test]
+    fn expand_by_number() {
+        assert_eq!(
+            expand("{{c2::Paris}} and {{c2::Lyon}} are in {{c1::France}}"),
+            vec![
+                card(1, "Paris and Lyon are in [...]", "France"),
+                card(2, "[...] and [...] are in France", "Paris, Lyon"),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_hinted() {
+        assert_eq!(
+            expand("{{c1::Paris::city}} is in {{c3::France::country}}"),
+            vec![
+                card(1, "[city] is in France", "Paris"),
+                card(3, "Paris is in [country]", "France"),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_nested() {
+        assert_eq!(
+            expand("{{c1::The capital {{c2::Paris::city}}}} is old"),
+            vec![
+                card(1, "[...] is old", "The capital Paris"),
+                card(2, "The capital [city] is old", "Paris"),
+            ]
+        );
+        assert_eq!(
+            expand_number("{{c1::a {{c2::b}} c::hint}}", 1),
+            Some((String::from("[hint]"), String::from("a b c")))
+        );
+    }
+
+    #[test]
+    fn braces_without_deletion() {
+        assert!(!has_cloze("{{c1}} and {{cx::y}}"));
+        assert_eq!(expand("{{c1::unclosed"), vec![]);
+        assert_eq!(expand_number("{{c1::a}}", 2), None);
+    }
+
+    #[test]
+    fn gaps_are_numbered_in_order() {
+        let answers = vec![String::from("learning"), String::from("flashcards")];
+        assert_eq!(
+            expand_gaps("For [...] with [...]", &answers),
+            vec![
+                card(1, "For [...] with flashcards", "learning"),
+                card(2, "For learning with [...]", "flashcards"),
+            ]
+        );
+        assert_eq!(expand_gaps("For [...]", &answers), vec![]);
+    }
+}
@@ -0,0 +1,364 @@
+//! Cards as CSV or TSV tables, for vocabulary lists and spreadsheets.
+//!
+//! A table is imported with a mapping of its columns to the question, the
+//! answer, the context title and the tags of the cards. The import can be
+//! previewed first, nothing is written until it is run.
+
+use std::{collections::HashSet, fmt, fs, path::Path};
+
+use crate::{database::Database, import::ImportError, org::FlashCard, sr::Stats};
+
+/// The columns of an exported table. The scheduling columns are ignored when
+/// the table is imported again.
+pub const COLUMNS: [&str; 11] = [
+    "question",
+    "answer",
+    "context",
+    "tags",
+    "interval",
+    "repetitions",
+    "ease",
+    "stability",
+    "difficulty",
+    "last_review",
+    "due",
+];
+
+/// TSV files are separated by tabs, everything else by commas.
+pub fn delimiter(path: &Path) -> char {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if matches!(extension.to_ascii_lowercase().as_str(), "tsv" | "tab") => '\t',
+        _ => ',',
+    }
+}
+
+/// Splits the text into records. Fields can be quoted with `"`, and a quoted
+/// field can hold delimiters, line breaks and `""` for a quote.
+pub fn parse(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            '\r' => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    // Blank lines hold no card.
+    records
+        .into_iter()
+        .filter(|record| record.iter().any(|field| !field.trim().is_empty()))
+        .collect()
+}
+
+/// Joins the fields into a line, quoting the fields that need it.
+fn record(fields: &[String], delimiter: char) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            if field.contains([delimiter, '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&delimiter.to_string())
+}
+
+/// Splits the tags of a field. They can be separated by spaces or commas, or
+/// written like org tags.
+pub fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.trim_start_matches('#').to_owned())
+        .collect()
+}
+
+/// Which column holds which part of a card.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub question: usize,
+    pub answer: usize,
+    pub context: Option<usize>,
+    pub tags: Option<usize>,
+    /// The first record holds the names of the columns.
+    pub header: bool,
+}
+
+impl Mapping {
+    /// Guesses the mapping from the names in the first record. Without known
+    /// names the first two columns are the question and the answer.
+    pub fn guess(first: &[String]) -> Self {
+        let find = |names: &[&str]| {
+            first
+                .iter()
+                .position(|name| names.contains(&name.trim().to_lowercase().as_str()))
+        };
+        let question = find(&["question", "questions", "front", "term", "word"]);
+        let answer = find(&["answer", "answers", "back", "definition", "meaning"]);
+        let context = find(&["context", "document", "deck", "title"]);
+        let tags = find(&["tags", "tag"]);
+        let header = question.is_some() || answer.is_some();
+        Mapping {
+            question: question.unwrap_or(0),
+            answer: answer.unwrap_or(1),
+            context,
+            tags,
+            header,
+        }
+    }
+}
+
+/// What an import does, or would do for a preview.
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    pub added: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    /// Records without a question or an answer.
+    pub invalid: usize,
+    /// The titles of the documents that are created.
+    pub documents: Vec<String>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} unchanged, {} invalid, {} new documents",
+            self.added,
+            self.changed,
+            self.unchanged,
+            self.invalid,
+            self.documents.len()
+        )
+    }
+}
+
+/// A card read from a record of the table.
+pub struct Row<'a> {
+    pub question: &'a str,
+    pub answer: &'a str,
+    pub context: &'a str,
+    pub tags: Vec<String>,
+}
+
+/// Reads the cards of the records with the mapping. Records without a
+/// question or an answer are `None`. Without a context column the cards go
+/// into the document titled `title`.
+pub fn rows<'a>(
+    records: &'a [Vec<String>],
+    mapping: &Mapping,
+    title: &'a str,
+) -> Vec<Option<Row<'a>>> {
+    let field = |record: &'a Vec<String>, column: Option<usize>| {
+        column
+            .and_then(|column| record.get(column))
+            .map(|field| field.trim())
+            .unwrap_or("")
+    };
+    records
+        .iter()
+        .skip(if mapping.header { 1 } else { 0 })
+        .map(|record| {
+            let question = field(record, Some(mapping.question));
+            let answer = field(record, Some(mapping.answer));
+            if question.is_empty() || answer.is_empty() {
+                return None;
+            }
+            let context = field(record, mapping.context);
+            Some(Row {
+                question,
+                answer,
+                context: if context.is_empty() { title } else { context },
+                tags: split_tags(field(record, mapping.tags)),
+            })
+        })
+        .collect()
+}
+
+/// Imports the cards of the table. A card with the question of a card that
+/// was imported from the same table updates its answer and tags, so an
+/// exported table can be edited and imported again. With `dry_run` nothing is
+/// written.
+pub fn import(
+    db: &Database,
+    records: &[Vec<String>],
+    mapping: &Mapping,
+    title: &str,
+    file_id: i64,
+    dry_run: bool,
+) -> Result<Summary, ImportError> {
+    let mut summary = Summary::default();
+    let mut seen = HashSet::new();
+    for row in rows(records, mapping, title) {
+        let row = match row {
+            Some(row) => row,
+            None => {
+                summary.invalid += 1;
+                continue;
+            }
+        };
+        // The question identifies a card, so a repeated one is skipped.
+        if !seen.insert(row.question) {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        // The rows of a context are the cards of a document of the table.
+        let doc_id = db.find_file_document(file_id, row.context)?;
+        let stored = match doc_id {
+            Some(id) => db.find_document_flashcard(id, row.question, &[])?,
+            None => None,
+        };
+        if let Some(stored) = stored {
+            let tags_changed = mapping.tags.is_some() && stored.tags != row.tags;
+            if stored.answers == row.answer && !tags_changed && !stored.retired {
+                summary.unchanged += 1;
+                continue;
+            }
+            summary.changed += 1;
+            if !dry_run {
+                let card = FlashCard::from_db(
+                    row.question,
+                    row.answer,
+                    stored.id,
+                    stored.uid,
+                    stored.doc_id,
+                    Stats::new(),
+                );
+                db.update_card_text(stored.id, &card)?;
+                if mapping.tags.is_some() {
+                    db.update_card_tags(stored.id, &row.tags)?;
+                }
+            }
+            continue;
+        }
+
+        if doc_id.is_none() && !summary.documents.iter().any(|title| title == row.context) {
+            summary.documents.push(row.context.to_owned());
+        }
+        summary.added += 1;
+        if dry_run {
+            continue;
+        }
+        let doc_id = match doc_id {
+            Some(id) => id,
+            None => db.insert_documents(
+                &format!("* {} :context:\n", row.context),
+                row.context,
+                file_id,
+            )?,
+        };
+        let mut card = FlashCard::from_db(row.question, row.answer, 0, None, doc_id, Stats::new());
+        card.set_tags(row.tags.iter().map(|tag| tag.as_str()));
+        db.insert_flashcard(&card)?;
+    }
+    Ok(summary)
+}
+
+/// Writes the cards of the document, or of every document, as a table with
+/// their schedule. Returns the number of cards.
+pub fn export(db: &Database, only: Option<i64>, path: &Path) -> Result<usize, ImportError> {
+    let delimiter = delimiter(path);
+    let header: Vec<String> = COLUMNS.iter().map(|name| name.to_string()).collect();
+    let mut lines = vec![record(&header, delimiter)];
+    for document in db.load_documents()? {
+        if only.is_some_and(|id| id != document.get_id()) {
+            continue;
+        }
+        for card in db.document_flashcards(document.get_id())? {
+            let stats = card.get_stats();
+            let due = card
+                .get_scheduled()
+                .map(|date| date.to_string())
+                .unwrap_or_default();
+            let fields = [
+                card.get_questions().to_owned(),
+                card.get_answers().to_owned(),
+                document.get_title().to_owned(),
+                card.get_tags().join(" "),
+                stats.interval.to_string(),
+                stats.num_reps.to_string(),
+                format!("{:.2}", stats.difficultly),
+                format!("{:.2}", stats.stability),
+                format!("{:.2}", stats.recall_difficulty),
+                stats
+                    .last_review
+                    .map(|date| date.to_string())
+                    .unwrap_or_default(),
+                due,
+            ];
+            lines.push(record(&fields, delimiter));
+        }
+    }
+    let count = lines.len() - 1;
+    fs::write(path, lines.join("\n") + "\n")?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(record: &[&str]) -> Vec<String> {
+        record.iter().map(|field| field.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_quoted_line_breaks() {
+        let text = "question,answer\r\n\"two\r\nlines\",\"a, \"\"quoted\"\" b\"\r\n\r\nlast,row";
+        assert_eq!(
+            parse(text, ','),
+            vec![
+                fields(&["question", "answer"]),
+                fields(&["two\r\nlines", "a, \"quoted\" b"]),
+                fields(&["last", "row"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_tabs_and_bom() {
+        let text = "\u{feff}Hund\tdog\nKatze\t\"cat\tkitten\"\n";
+        assert_eq!(
+            parse(text, '\t'),
+            vec![fields(&["Hund", "dog"]), fields(&["Katze", "cat\tkitten"])]
+        );
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let original = fields(&["plain", "with,comma", "say \"hi\"", "line\r\nbreak"]);
+        let line = record(&original, ',');
+        assert_eq!(
+            line,
+            "plain,\"with,comma\",\"say \"\"hi\"\"\",\"line\r\nbreak\""
+        );
+        assert_eq!(parse(&line, ','), vec![original]);
+    }
+}
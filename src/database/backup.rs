@@ -0,0 +1,162 @@
+//! Rotating snapshots of the database, taken before imports and after review
+//! sessions, so a bad import never loses the schedule of the cards.
+//!
+//! The snapshots of `default.db3` are kept in `backups/default/` next to it,
+//! named after the time they were taken.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, Utc};
+use rusqlite::{Connection, DatabaseName, OpenFlags, Result};
+
+use super::{migrations::MigrationError, stats::CollectionStats, Database};
+
+/// The number of snapshots kept when there is no setting for it.
+pub const DEFAULT_BACKUPS: usize = 10;
+
+/// A snapshot in the backup directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub taken_at: DateTime<Local>,
+}
+
+impl Backup {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// What a snapshot holds, shown before it is restored.
+#[derive(Debug, Default)]
+pub struct BackupSummary {
+    pub stats: CollectionStats,
+    pub last_review: Option<DateTime<Utc>>,
+}
+
+impl Database {
+    /// The path of the database file, which is `None` for a database in
+    /// memory.
+    fn file_path(&self) -> Option<PathBuf> {
+        self.conn
+            .path()
+            .filter(|path| !path.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+    }
+
+    fn backup_dir(&self) -> Option<(PathBuf, String)> {
+        let path = self.file_path()?;
+        let stem = path.file_stem()?.to_string_lossy().into_owned();
+        let dir = path.parent()?.join("backups").join(&stem);
+        Some((dir, stem))
+    }
+
+    /// Takes a snapshot of the database with the online backup API, so it is
+    /// consistent even while the database is written. Only the latest `keep`
+    /// snapshots are kept, and none is taken when `keep` is 0.
+    pub fn back_up(&self, keep: usize) -> Result<Option<PathBuf>> {
+        self.back_up_except(keep, None)
+    }
+
+    /// Like `back_up`, but never removes the snapshot at `except`.
+    fn back_up_except(&self, keep: usize, except: Option<&Path>) -> Result<Option<PathBuf>> {
+        let (dir, stem) = match self.backup_dir() {
+            Some(it) if keep > 0 => it,
+            _ => return Ok(None),
+        };
+        if let Err(err) = fs::create_dir_all(&dir) {
+            println!("{}: {}", dir.display(), err);
+        }
+        let name = format!("{}-{}", stem, Local::now().format("%Y%m%d-%H%M%S"));
+        let mut path = dir.join(format!("{}.db3", name));
+        // Two snapshots in the same second get a counter.
+        let mut count = 1;
+        while path.exists() {
+            count += 1;
+            path = dir.join(format!("{}-{}.db3", name, count));
+        }
+        self.conn.backup(DatabaseName::Main, &path, None)?;
+
+        for old in self.backups().into_iter().skip(keep) {
+            if Some(old.path.as_path()) == except {
+                continue;
+            }
+            if let Err(err) = fs::remove_file(&old.path) {
+                println!("{}: {}", old.path.display(), err);
+            }
+        }
+        Ok(Some(path))
+    }
+
+    /// The snapshots of the database, the latest first.
+    pub fn backups(&self) -> Vec<Backup> {
+        let (dir, stem) = match self.backup_dir() {
+            Some(it) => it,
+            None => return vec![],
+        };
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let prefix = format!("{}-", stem);
+        let mut backups: Vec<Backup> = entries
+            .flatten()
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                name.starts_with(&prefix) && name.ends_with(".db3")
+            })
+            .map(|entry| Backup {
+                path: entry.path(),
+                taken_at: entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+                    .into(),
+            })
+            .collect();
+        // The names hold the time, so they sort in the order of the snapshots.
+        // The stems are compared, as a counter comes before the extension.
+        backups.sort_by(|a, b| b.path.file_stem().cmp(&a.path.file_stem()));
+        backups
+    }
+
+    /// Replaces the cards, documents and reviews with those of a snapshot.
+    /// The current state is backed up first, so the restore can be undone,
+    /// and a snapshot of an older schema is migrated after it is restored.
+    pub fn restore(&mut self, backup: &Path, keep: usize) -> Result<(), MigrationError> {
+        let path = self.file_path().unwrap_or_default();
+        let source = Connection::open_with_flags(backup, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let version: i64 = source.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let supported = Database::schema_version();
+        if version > supported {
+            return Err(MigrationError::Newer { version, supported });
+        }
+        drop(source);
+
+        self.back_up_except(keep.max(1), Some(backup))?;
+        self.conn.restore(
+            DatabaseName::Main,
+            backup,
+            None::<fn(rusqlite::backup::Progress)>,
+        )?;
+        Database::migrate(&mut self.conn, &path)
+    }
+}
+
+/// Reads the counts of a snapshot without changing it.
+pub fn inspect_backup(path: &Path) -> Result<BackupSummary> {
+    let db = Database {
+        conn: Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?,
+    };
+    Ok(BackupSummary {
+        stats: db.collection_stats()?,
+        last_review: db.last_review()?,
+    })
+}
@@ -1,13 +1,8 @@
-use rusqlite::{Column, Connection, Result};
-use sea_query::{
-    ColumnDef, ForeignKey, ForeignKeyAction, SqliteQueryBuilder,
-    Table,
-};
+use std::{fs, path::Path};
 
-use super::flashcards::FlashCards;
-use super::documents::Documents;
-use super::files::Files;
-sea_query::sea_query_driver_rusqlite!();
+use rusqlite::Connection;
+
+use super::migrations::MigrationError;
 
 #[derive(Debug)]
 pub struct Database {
@@ -23,76 +18,19 @@ impl Default for Database {
 }
 
 impl Database {
-    pub fn connect() -> Result<Self> {
-        if let Err(err) = Database::create_tables() {
-            println!("Error: {:?}", err);
-        }
-        Ok(Database {
-            conn: Connection::open("./database.db3")?,
-        })
-    }
-
-    pub fn create_tables() -> Result<()> {
-        let conn = Connection::open("./database.db3")?;
-        // If table doesn't exists create table.
-        let queries = [
-            Table::create()
-                .table(Files::Table)
-                .if_not_exists()
-                .col(
-                    ColumnDef::new(Files::Id)
-                        .integer()
-                        .not_null()
-                        .auto_increment()
-                        .primary_key(),
-                )
-                .col(ColumnDef::new(Files::Path).string().unique_key())
-                .col(ColumnDef::new(Files::Name).string())
-                .build(SqliteQueryBuilder),
-            Table::create()
-                .table(Documents::Table)
-                .if_not_exists()
-                .col(
-                    ColumnDef::new(Documents::Id)
-                        .integer()
-                        .not_null()
-                        .auto_increment()
-                        .primary_key(),
-                )
-                .col(ColumnDef::new(Documents::Title).string().unique_key())
-                .col(ColumnDef::new(Documents::Content).string())
-                .build(SqliteQueryBuilder),
-            Table::create()
-                .table(FlashCards::Table)
-                .if_not_exists()
-                .col(
-                    ColumnDef::new(FlashCards::Id)
-                        .integer()
-                        .auto_increment()
-                        .primary_key(),
-                )
-                .col(ColumnDef::new(FlashCards::Questions).string().unique_key())
-                .col(ColumnDef::new(FlashCards::Answers).string())
-                .col(ColumnDef::new(FlashCards::DocId).integer())
-                .col(ColumnDef::new(FlashCards::Difficulty).float())
-                .col(ColumnDef::new(FlashCards::Interval).integer())
-                .col(ColumnDef::new(FlashCards::Reps).integer())
-                .col(ColumnDef::new(FlashCards::Created).date())
-                .col(ColumnDef::new(FlashCards::Scheduled).date())
-                .foreign_key(
-                    ForeignKey::create()
-                        .from(FlashCards::Table, FlashCards::DocId)
-                        .to(Documents::Table, Documents::Id)
-                        .on_delete(ForeignKeyAction::Cascade)
-                        .on_update(ForeignKeyAction::Cascade),
-                )
-                .build(SqliteQueryBuilder),
-        ]
-        .join(";");
-        conn.execute_batch(&queries)?;
-        if let Ok(_) = conn.close() {
-            println!("Connection closed")
+    /// Opens the database at `path`, and creates it with its directory when
+    /// it doesn't exist yet. The schema of an older database is migrated to
+    /// the current version first.
+    pub fn connect(path: &Path) -> Result<Self, MigrationError> {
+        if let Some(parent) = path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                println!("{}: {}", parent.display(), err);
+            }
         }
-        Ok(())
+        let mut conn = Connection::open(path)?;
+        // SQLite checks the foreign keys only when a connection asks for it.
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Database::migrate(&mut conn, path)?;
+        Ok(Database { conn })
     }
 }
@@ -0,0 +1,180 @@
+//! Decks group the cards by where they come from. A file holds the documents
+//! imported from it, and the cards of a document are grouped by their tags.
+
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDate, Utc};
+use fallible_iterator::FallibleIterator;
+use rusqlite::Result;
+use sea_query::{Condition, Expr, Order, Query, SqliteQueryBuilder};
+
+sea_query::sea_query_driver_rusqlite!();
+use sea_query_driver_rusqlite::RusqliteValues;
+
+use super::{documents::Documents, flashcards::FlashCards, Database};
+
+/// Identifies a deck, to pick the cards of a session.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeckKey {
+    File(i64),
+    Document(i64),
+    /// The cards of a document that have the tag.
+    Tag(i64, String),
+}
+
+impl DeckKey {
+    /// The condition on the `flashcards` table for the cards of the deck.
+    pub(super) fn condition(&self) -> Condition {
+        match self {
+            DeckKey::File(id) => Condition::all().add(
+                Expr::col(FlashCards::DocId).in_subquery(
+                    Query::select()
+                        .column(Documents::Id)
+                        .from(Documents::Table)
+                        .and_where(Expr::col(Documents::File).eq(*id))
+                        .take(),
+                ),
+            ),
+            DeckKey::Document(id) => Condition::all().add(Expr::col(FlashCards::DocId).eq(*id)),
+            // The tags are stored separated by spaces.
+            DeckKey::Tag(id, tag) => Condition::all()
+                .add(Expr::col(FlashCards::DocId).eq(*id))
+                .add(Expr::cust_with_values(
+                    "instr(' ' || tags || ' ', ?) > 0",
+                    vec![format!(" {} ", tag)],
+                )),
+        }
+    }
+}
+
+/// The cards of a deck that are not retired.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeckCounts {
+    pub due: i64,
+    /// Cards that were never answered.
+    pub new: i64,
+    pub total: i64,
+}
+
+impl DeckCounts {
+    fn add(&mut self, other: &DeckCounts) {
+        self.due += other.due;
+        self.new += other.new;
+        self.total += other.total;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Deck {
+    pub key: DeckKey,
+    pub name: String,
+    pub counts: DeckCounts,
+    pub children: Vec<Deck>,
+}
+
+/// The counts of a document, and of each of its tags.
+#[derive(Default)]
+struct DocumentCounts {
+    counts: DeckCounts,
+    tags: BTreeMap<String, DeckCounts>,
+}
+
+impl Database {
+    /// Counts the cards that are not retired by document and tag.
+    fn document_counts(&self) -> Result<BTreeMap<i64, DocumentCounts>> {
+        let (sql, values) = Query::select()
+            .columns(vec![
+                FlashCards::DocId,
+                FlashCards::Tags,
+                FlashCards::Scheduled,
+                FlashCards::Reviewed,
+                FlashCards::Interval,
+            ])
+            .from(FlashCards::Table)
+            .and_where(Expr::col(FlashCards::Retired).eq(false))
+            .build(SqliteQueryBuilder);
+
+        let today = Utc::now().date().naive_local();
+        let mut documents: BTreeMap<i64, DocumentCounts> = BTreeMap::new();
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        while let Some(row) = rows.next()? {
+            let tags: Option<String> = row.get(1)?;
+            let scheduled: Option<NaiveDate> = row.get(2)?;
+            let reviewed: Option<NaiveDate> = row.get(3)?;
+            let interval: Option<i64> = row.get(4)?;
+            let card = DeckCounts {
+                due: scheduled.map_or(0, |date| (date <= today) as i64),
+                new: (reviewed.is_none() && interval.unwrap_or(0) == 0) as i64,
+                total: 1,
+            };
+            let document = documents.entry(row.get(0)?).or_default();
+            document.counts.add(&card);
+            for tag in tags.iter().flat_map(|tags| tags.split_whitespace()) {
+                document.tags.entry(tag.to_owned()).or_default().add(&card);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// The decks of the files, with the decks of their documents and tags
+    /// below them, ordered by name. Decks without cards are left out, and
+    /// documents without a file are decks of their own.
+    pub fn decks(&self) -> Result<Vec<Deck>> {
+        let mut counts = self.document_counts()?;
+        let (sql, values) = Query::select()
+            .columns(vec![Documents::Id, Documents::Title, Documents::File])
+            .from(Documents::Table)
+            .order_by(Documents::Title, Order::Asc)
+            .build(SqliteQueryBuilder);
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        let documents: Vec<(i64, String, Option<i64>)> = rows
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .collect()?;
+
+        let mut files: BTreeMap<Option<i64>, Vec<Deck>> = BTreeMap::new();
+        for (id, title, file) in documents {
+            let document = match counts.remove(&id) {
+                Some(document) => document,
+                None => continue,
+            };
+            let children = document
+                .tags
+                .into_iter()
+                .map(|(tag, counts)| Deck {
+                    key: DeckKey::Tag(id, tag.clone()),
+                    name: tag,
+                    counts,
+                    children: vec![],
+                })
+                .collect();
+            files.entry(file).or_default().push(Deck {
+                key: DeckKey::Document(id),
+                name: title,
+                counts: document.counts,
+                children,
+            });
+        }
+
+        let mut decks = vec![];
+        for file in self.load_file_names()? {
+            if let Some(children) = files.remove(&Some(file.get_id())) {
+                let mut counts = DeckCounts::default();
+                for child in &children {
+                    counts.add(&child.counts);
+                }
+                decks.push(Deck {
+                    key: DeckKey::File(file.get_id()),
+                    name: file.get_name().to_owned(),
+                    counts,
+                    children,
+                });
+            }
+        }
+        decks.sort_by(|a, b| a.name.cmp(&b.name));
+        // The documents of files that are gone from the table.
+        decks.extend(files.into_values().flatten());
+        Ok(decks)
+    }
+}
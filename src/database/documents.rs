@@ -1,19 +1,20 @@
 use rand::Error;
 use rusqlite::{Result, Row};
-use sea_query::{Expr, Iden, Query, SqliteQueryBuilder};
+use sea_query::{Condition, Expr, Iden, Order, Query, SqliteQueryBuilder};
 
 use fallible_iterator::FallibleIterator;
 sea_query::sea_query_driver_rusqlite!();
 use crate::org::Document;
 use sea_query_driver_rusqlite::RusqliteValues;
 
-use super::Database;
+use super::{files::Files, Database};
 
 pub enum Documents {
     Table,
     Id,
     Title,
     Content,
+    File,
 }
 
 impl Iden for Documents {
@@ -26,6 +27,7 @@ impl Iden for Documents {
                 Documents::Id => "id",
                 Documents::Title => "title",
                 Documents::Content => "content",
+                Documents::File => "file",
             }
         )
         .unwrap();
@@ -33,11 +35,11 @@ impl Iden for Documents {
 }
 
 impl Database {
-    pub fn insert_documents(&self, document: &str, title: &str) -> Result<i64> {
+    pub fn insert_documents(&self, document: &str, title: &str, file_id: i64) -> Result<i64> {
         let (sql, values) = Query::insert()
             .into_table(Documents::Table)
-            .columns(vec![Documents::Content, Documents::Title])
-            .values_panic(vec![document.into(), title.into()])
+            .columns(vec![Documents::Content, Documents::Title, Documents::File])
+            .values_panic(vec![document.into(), title.into(), file_id.into()])
             .build(SqliteQueryBuilder);
 
         self.conn.execute(
@@ -47,13 +49,87 @@ impl Database {
         Ok(self.conn.last_insert_rowid())
     }
 
-    pub fn get_last_id(&self) -> i64 {
-        let data: Result<i64> = self.conn.query_row(
-            "SELECT id FROM documents ORDER BY id DESC LIMIT 1",
-            [],
-            |row| row.get(0),
-        );
-        data.expect("Cannot fetch the last id.")
+    pub fn update_document(&self, id: i64, document: &str, title: &str, file_id: i64) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(Documents::Table)
+            .values(vec![
+                (Documents::Content, document.into()),
+                (Documents::Title, title.into()),
+                (Documents::File, file_id.into()),
+            ])
+            .and_where(Expr::col(Documents::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// Finds the document that was imported from the file. Documents imported
+    /// before files were tracked have no file, they are found by their title.
+    /// A document of another file is never matched, even with the same title.
+    pub fn find_document(&self, file_id: i64, title: &str) -> Result<Option<i64>> {
+        let (sql, values) = Query::select()
+            .column(Documents::Id)
+            .from(Documents::Table)
+            .cond_where(
+                Condition::any()
+                    .add(Expr::col(Documents::File).eq(file_id))
+                    .add(
+                        Condition::all()
+                            .add(Expr::col(Documents::File).is_null())
+                            .add(Expr::col(Documents::Title).eq(title)),
+                    ),
+            )
+            .order_by_expr(Expr::col(Documents::File).eq(file_id), Order::Desc)
+            .limit(1)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds a document of the file by its title. A table holds a document
+    /// for every context of its rows.
+    pub fn find_file_document(&self, file_id: i64, title: &str) -> Result<Option<i64>> {
+        let (sql, values) = Query::select()
+            .column(Documents::Id)
+            .from(Documents::Table)
+            .and_where(Expr::col(Documents::File).eq(file_id))
+            .and_where(Expr::col(Documents::Title).eq(title))
+            .limit(1)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds a document by its title only. Used for the decks of a package,
+    /// which share the file they were imported from.
+    pub fn find_titled_document(&self, title: &str) -> Result<Option<i64>> {
+        let (sql, values) = Query::select()
+            .column(Documents::Id)
+            .from(Documents::Table)
+            .and_where(Expr::col(Documents::Title).eq(title))
+            .limit(1)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
     }
 
     pub fn load_data(&self, id: i64) -> Result<Vec<Document>> {
@@ -67,4 +143,37 @@ impl Database {
         let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
         rows.map(|row| Ok(Document::from(row))).collect()
     }
+
+    pub fn load_documents(&self) -> Result<Vec<Document>> {
+        let (sql, values) = Query::select()
+            .columns(vec![Documents::Id, Documents::Title, Documents::Content])
+            .from(Documents::Table)
+            .order_by(Documents::Title, Order::Asc)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        rows.map(|row| Ok(Document::from(row))).collect()
+    }
+
+    /// The id and path of the file the document was imported from.
+    pub fn document_file(&self, id: i64) -> Result<Option<(i64, String)>> {
+        let (sql, values) = Query::select()
+            .column((Files::Table, Files::Id))
+            .column((Files::Table, Files::Path))
+            .from(Documents::Table)
+            .inner_join(
+                Files::Table,
+                Expr::tbl(Documents::Table, Documents::File).equals(Files::Table, Files::Id),
+            )
+            .and_where(Expr::tbl(Documents::Table, Documents::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some((row.get(0)?, row.get(1)?))),
+            None => Ok(None),
+        }
+    }
 }
@@ -2,7 +2,7 @@ use std::path::Path;
 
 use fallible_iterator::FallibleIterator;
 use rusqlite::Result;
-use sea_query::{Iden, Query, SqliteQueryBuilder};
+use sea_query::{Expr, Iden, Query, SqliteQueryBuilder};
 
 sea_query::sea_query_driver_rusqlite!();
 use super::Database;
@@ -14,6 +14,8 @@ pub enum Files {
     Table,
     Name,
     Path,
+    Hash,
+    Modified,
 }
 
 impl Iden for Files {
@@ -26,6 +28,8 @@ impl Iden for Files {
                 Files::Table => "files",
                 Files::Name => "name",
                 Files::Path => "path",
+                Files::Hash => "hash",
+                Files::Modified => "modified_at",
             }
         )
         .unwrap();
@@ -33,15 +37,67 @@ impl Iden for Files {
 }
 
 impl Database {
-    pub fn insert_file(&self, file: &Path) -> Result<()> {
+    /// The id of the file, if it was imported before.
+    pub fn find_file(&self, file: &Path) -> Result<Option<i64>> {
         let mut data = File::new();
         data.load_path(file);
+        let (sql, values) = Query::select()
+            .column(Files::Id)
+            .from(Files::Table)
+            .and_where(Expr::col(Files::Path).eq(data.get_path()))
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Inserts the file if it is new, and returns its id.
+    pub fn insert_file(&self, file: &Path) -> Result<i64> {
+        if let Some(id) = self.find_file(file)? {
+            return Ok(id);
+        }
+        let mut data = File::new();
+        data.load_path(file);
+
         let (sql, values) = Query::insert()
             .into_table(Files::Table)
             .columns(vec![Files::Name, Files::Path])
             .values_panic(vec![data.get_name().into(), data.get_path().into()])
             .build(SqliteQueryBuilder);
 
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// The content hash and modification time recorded at the last import.
+    pub fn load_file_state(&self, id: i64) -> Result<(Option<String>, Option<i64>)> {
+        let (sql, values) = Query::select()
+            .columns(vec![Files::Hash, Files::Modified])
+            .from(Files::Table)
+            .and_where(Expr::col(Files::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.query_row(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    pub fn update_file_state(&self, id: i64, hash: &str, modified: i64) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(Files::Table)
+            .values(vec![(Files::Hash, hash.into()), (Files::Modified, modified.into())])
+            .and_where(Expr::col(Files::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
         self.conn.execute(
             sql.as_str(),
             RusqliteValues::from(values).as_params().as_slice(),
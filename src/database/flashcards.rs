@@ -1,18 +1,19 @@
-use chrono::{Duration, Utc};
+use chrono::{Duration, NaiveDate, Utc};
 use fallible_iterator::FallibleIterator;
-use rusqlite::Result;
-use sea_query::{tests_cfg::Character, Expr, Iden, Order, Query, SqliteQueryBuilder};
+use rusqlite::{Result, Row};
+use sea_query::{tests_cfg::Character, Condition, Expr, Iden, Order, Query, SqliteQueryBuilder};
 
 sea_query::sea_query_driver_rusqlite!();
 use sea_query_driver_rusqlite::RusqliteValues;
 
-use crate::org::FlashCard;
+use crate::{org::FlashCard, sr::Stats};
 
-use super::Database;
+use super::{decks::DeckKey, Database};
 
 pub enum FlashCards {
     Table,
     Id,
+    Uid,
     DocId,
     Questions,
     Answers,
@@ -21,6 +22,25 @@ pub enum FlashCards {
     Difficulty,
     Created,
     Scheduled,
+    Stability,
+    RecallDifficulty,
+    Reviewed,
+    Retired,
+    Tags,
+    DrillRepeats,
+}
+
+/// The text of a card as it is stored, used to find what changed in a file.
+#[derive(Debug)]
+pub struct StoredCard {
+    pub id: i64,
+    pub uid: Option<String>,
+    pub doc_id: i64,
+    pub questions: String,
+    pub answers: String,
+    pub retired: bool,
+    pub tags: Vec<String>,
+    pub drill_repeats: i64,
 }
 
 impl Iden for FlashCards {
@@ -31,6 +51,7 @@ impl Iden for FlashCards {
             match self {
                 FlashCards::Table => "flashcards",
                 FlashCards::Id => "id",
+                FlashCards::Uid => "uid",
                 FlashCards::DocId => "document",
                 FlashCards::Questions => "questions",
                 FlashCards::Answers => "answers",
@@ -39,6 +60,12 @@ impl Iden for FlashCards {
                 FlashCards::Interval => "intervals",
                 FlashCards::Created => "created_at",
                 FlashCards::Scheduled => "scheduled_at",
+                FlashCards::Stability => "stability",
+                FlashCards::RecallDifficulty => "recall_difficulty",
+                FlashCards::Reviewed => "reviewed_at",
+                FlashCards::Retired => "retired",
+                FlashCards::Tags => "tags",
+                FlashCards::DrillRepeats => "drill_repeats",
             }
         )
         .unwrap();
@@ -46,37 +73,198 @@ impl Iden for FlashCards {
 }
 
 impl Database {
-    pub fn insert_flashcards(&self, cards: &Vec<FlashCard>) -> Result<()> {
-        for card in cards {
-            let (sql, values) = Query::insert()
-                .into_table(FlashCards::Table)
-                .columns(vec![
-                    FlashCards::Questions,
-                    FlashCards::Answers,
-                    FlashCards::DocId,
-                    FlashCards::Difficulty,
-                    FlashCards::Reps,
-                    FlashCards::Interval,
-                    FlashCards::Created,
-                    FlashCards::Scheduled,
-                ])
-                .values_panic(vec![
-                    card.get_questions().into(),
-                    card.get_answers().into(),
-                    card.get_doc_id().into(),
-                    card.get_stats().difficultly.into(),
-                    card.get_stats().num_reps.into(),
-                    card.get_stats().interval.into(),
-                    Utc::now().date().naive_local().into(),
-                    Utc::now().date().naive_local().into(),
-                ])
-                .build(SqliteQueryBuilder);
+    pub fn insert_flashcard(&self, card: &FlashCard) -> Result<i64> {
+        let (sql, values) = Query::insert()
+            .into_table(FlashCards::Table)
+            .columns(vec![
+                FlashCards::Uid,
+                FlashCards::Questions,
+                FlashCards::Answers,
+                FlashCards::DocId,
+                FlashCards::Difficulty,
+                FlashCards::Reps,
+                FlashCards::Interval,
+                FlashCards::Created,
+                FlashCards::Scheduled,
+                FlashCards::Stability,
+                FlashCards::RecallDifficulty,
+                FlashCards::Reviewed,
+                FlashCards::Retired,
+                FlashCards::Tags,
+                FlashCards::DrillRepeats,
+            ])
+            .values_panic(vec![
+                card.get_uid().into(),
+                card.get_questions().into(),
+                card.get_answers().into(),
+                card.get_doc_id().into(),
+                card.get_stats().difficultly.into(),
+                card.get_stats().num_reps.into(),
+                card.get_stats().interval.into(),
+                Utc::now().date().naive_local().into(),
+                card.get_scheduled()
+                    .unwrap_or_else(|| Utc::now().date().naive_local())
+                    .into(),
+                card.get_stats().stability.into(),
+                card.get_stats().recall_difficulty.into(),
+                card.get_stats().last_review.into(),
+                false.into(),
+                card.get_tags().join(" ").into(),
+                card.get_drill_repeats().into(),
+            ])
+            .build(SqliteQueryBuilder);
 
-            self.conn.execute(
-                sql.as_str(),
-                RusqliteValues::from(values).as_params().as_slice(),
-            )?;
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Looks up a card, including a retired one, by its ID or its question.
+    pub fn find_flashcard(&self, column: FlashCards, value: &str) -> Result<Option<StoredCard>> {
+        self.find_stored_card(Condition::all().add(Expr::col(column).eq(value)))
+    }
+
+    /// Looks up a card of the document that has no ID yet by its question.
+    /// The cards of other documents can have the same question. The cards in
+    /// `except` are skipped, so the cards of a document with the same question
+    /// are found in the order they were stored.
+    pub fn find_document_flashcard(
+        &self,
+        doc_id: i64,
+        questions: &str,
+        except: &[i64],
+    ) -> Result<Option<StoredCard>> {
+        let mut condition = Condition::all()
+            .add(Expr::col(FlashCards::DocId).eq(doc_id))
+            .add(Expr::col(FlashCards::Uid).is_null())
+            .add(Expr::col(FlashCards::Questions).eq(questions));
+        if !except.is_empty() {
+            condition = condition.add(Expr::col(FlashCards::Id).is_not_in(except.iter().copied()));
         }
+        self.find_stored_card(condition)
+    }
+
+    fn find_stored_card(&self, condition: Condition) -> Result<Option<StoredCard>> {
+        let (sql, values) = Query::select()
+            .columns(vec![
+                FlashCards::Id,
+                FlashCards::Uid,
+                FlashCards::DocId,
+                FlashCards::Questions,
+                FlashCards::Answers,
+                FlashCards::Retired,
+                FlashCards::Tags,
+                FlashCards::DrillRepeats,
+            ])
+            .from(FlashCards::Table)
+            .cond_where(condition)
+            .order_by(FlashCards::Id, Order::Asc)
+            .limit(1)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some(StoredCard {
+                id: row.get(0)?,
+                uid: row.get(1)?,
+                doc_id: row.get(2)?,
+                questions: row.get(3)?,
+                answers: row.get(4)?,
+                retired: row.get::<_, Option<bool>>(5)?.unwrap_or(false),
+                tags: row
+                    .get::<_, Option<String>>(6)?
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(|tag| tag.to_owned())
+                    .collect(),
+                drill_repeats: row.get::<_, Option<i64>>(7)?.unwrap_or(0),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Ids of the cards of a document that are not retired.
+    pub fn active_flashcards(&self, doc_id: i64) -> Result<Vec<i64>> {
+        let (sql, values) = Query::select()
+            .column(FlashCards::Id)
+            .from(FlashCards::Table)
+            .and_where(Expr::col(FlashCards::DocId).eq(doc_id))
+            .and_where(Expr::col(FlashCards::Retired).eq(false))
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        rows.map(|row| row.get(0)).collect()
+    }
+
+    /// Retired cards are kept with their history, but are never due.
+    pub fn retire_flashcard(&self, id: i64) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(FlashCards::Table)
+            .values(vec![(FlashCards::Retired, true.into())])
+            .and_where(Expr::col(FlashCards::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// Updates the text of a card and brings it back if it was retired. The
+    /// stats are left untouched.
+    pub fn update_card_text(&self, id: i64, card: &FlashCard) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(FlashCards::Table)
+            .values(vec![
+                (FlashCards::Uid, card.get_uid().into()),
+                (FlashCards::Questions, card.get_questions().into()),
+                (FlashCards::Answers, card.get_answers().into()),
+                (FlashCards::DocId, card.get_doc_id().into()),
+                (FlashCards::Retired, false.into()),
+            ])
+            .and_where(Expr::col(FlashCards::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// Sets the number of times the card was drilled in org-drill before its
+    /// answers in the review log.
+    pub fn update_drill_repeats(&self, id: i64, repeats: i64) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(FlashCards::Table)
+            .values(vec![(FlashCards::DrillRepeats, repeats.into())])
+            .and_where(Expr::col(FlashCards::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the tags of a card.
+    pub fn update_card_tags(&self, id: i64, tags: &[String]) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(FlashCards::Table)
+            .values(vec![(FlashCards::Tags, tags.join(" ").into())])
+            .and_where(Expr::col(FlashCards::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
         Ok(())
     }
 
@@ -88,6 +276,12 @@ impl Database {
                     (FlashCards::Difficulty, card.get_stats().difficultly.into()),
                     (FlashCards::Reps, card.get_stats().num_reps.into()),
                     (FlashCards::Interval, card.get_stats().interval.into()),
+                    (FlashCards::Stability, card.get_stats().stability.into()),
+                    (
+                        FlashCards::RecallDifficulty,
+                        card.get_stats().recall_difficulty.into(),
+                    ),
+                    (FlashCards::Reviewed, card.get_stats().last_review.into()),
                     (
                         FlashCards::Scheduled,
                         (Utc::now().date() + Duration::days(card.get_stats().interval))
@@ -108,38 +302,168 @@ impl Database {
         }
     }
 
-    pub fn get_flashcards(&self, num: i32) -> Result<Vec<FlashCard>> {
-        // Fetch the card that has scheduled date as today.
+    /// The columns read by `card_from_row`.
+    fn card_columns() -> Vec<FlashCards> {
+        vec![
+            FlashCards::Id,
+            FlashCards::Questions,
+            FlashCards::Answers,
+            FlashCards::DocId,
+            FlashCards::Difficulty,
+            FlashCards::Interval,
+            FlashCards::Reps,
+            FlashCards::Stability,
+            FlashCards::RecallDifficulty,
+            FlashCards::Reviewed,
+            FlashCards::Uid,
+            FlashCards::Scheduled,
+            FlashCards::Tags,
+            FlashCards::DrillRepeats,
+        ]
+    }
+
+    fn card_from_row(row: &Row) -> Result<FlashCard> {
+        let id: i64 = row.get(0)?;
+        let questions: String = row.get(1)?;
+        let answers: String = row.get(2)?;
+        let doc_id: i64 = row.get(3)?;
+        let stats = Stats {
+            difficultly: row.get(4)?,
+            interval: row.get(5)?,
+            num_reps: row.get(6)?,
+            stability: row.get::<_, Option<f64>>(7)?.unwrap_or(0.0),
+            recall_difficulty: row.get::<_, Option<f64>>(8)?.unwrap_or(0.0),
+            last_review: row.get(9)?,
+        };
+        let mut card = FlashCard::from_db(&questions, &answers, id, row.get(10)?, doc_id, stats);
+        card.set_scheduled(row.get(11)?);
+        let tags: Option<String> = row.get(12)?;
+        card.set_tags(tags.iter().flat_map(|tags| tags.split_whitespace()));
+        card.set_drill_repeats(row.get::<_, Option<i64>>(13)?.unwrap_or(0));
+        Ok(card)
+    }
+
+    /// Fetches the due queue. Every card scheduled on or before today is due,
+    /// so the days that were missed are not lost. The most overdue cards come
+    /// first and at most `num` cards are returned.
+    pub fn get_due_flashcards(&self, num: i32) -> Result<Vec<FlashCard>> {
+        self.get_due_deck_flashcards(num, &[])
+    }
+
+    /// Fetches the due queue of the cards in any of the decks, or of every
+    /// card without decks.
+    pub fn get_due_deck_flashcards(&self, num: i32, decks: &[DeckKey]) -> Result<Vec<FlashCard>> {
+        let mut query = Query::select();
+        query
+            .columns(Database::card_columns())
+            .from(FlashCards::Table)
+            .and_where(Expr::col(FlashCards::Scheduled).lte(Utc::now().date().naive_local()))
+            .and_where(Expr::col(FlashCards::Retired).eq(false));
+        if !decks.is_empty() {
+            query.cond_where(
+                decks
+                    .iter()
+                    .fold(Condition::any(), |any, deck| any.add(deck.condition())),
+            );
+        }
+        let (sql, values) = query
+            .order_by(FlashCards::Scheduled, Order::Asc)
+            .order_by(FlashCards::Difficulty, Order::Desc)
+            .limit(num as u64)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        rows.map(Database::card_from_row).collect()
+    }
+
+    /// A card with its schedule, retired or not.
+    pub fn get_flashcard(&self, id: i64) -> Result<Option<FlashCard>> {
+        let (sql, values) = Query::select()
+            .columns(Database::card_columns())
+            .from(FlashCards::Table)
+            .and_where(Expr::col(FlashCards::Id).eq(id))
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => Ok(Some(Database::card_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every card of the document that is not retired, with its schedule.
+    pub fn document_flashcards(&self, doc_id: i64) -> Result<Vec<FlashCard>> {
+        let (sql, values) = Query::select()
+            .columns(Database::card_columns())
+            .from(FlashCards::Table)
+            .and_where(Expr::col(FlashCards::DocId).eq(doc_id))
+            .and_where(Expr::col(FlashCards::Retired).eq(false))
+            .order_by(FlashCards::Id, Order::Asc)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        rows.map(Database::card_from_row).collect()
+    }
+
+    /// Seeds the FSRS state of every card that has none from its SM-2 stats.
+    /// Cards that were never reviewed by FSRS get their last review from the
+    /// scheduled date and interval. Returns the number of seeded cards.
+    pub fn seed_fsrs_state(&self) -> Result<usize> {
         let (sql, values) = Query::select()
             .columns(vec![
                 FlashCards::Id,
-                FlashCards::Questions,
-                FlashCards::Answers,
-                FlashCards::DocId,
                 FlashCards::Difficulty,
                 FlashCards::Interval,
                 FlashCards::Reps,
+                FlashCards::Scheduled,
+                FlashCards::Reviewed,
             ])
             .from(FlashCards::Table)
-            .and_where(Expr::col(FlashCards::Scheduled).eq(Utc::now().date().naive_local()))
-            .order_by(FlashCards::Difficulty, Order::Desc)
-            .limit(num as u64)
+            .cond_where(
+                Condition::any()
+                    .add(Expr::col(FlashCards::Stability).is_null())
+                    .add(Expr::col(FlashCards::Stability).lte(0.0)),
+            )
             .build(SqliteQueryBuilder);
 
         let mut stmt = self.conn.prepare(sql.as_str())?;
         let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
-        rows.map(|row| {
-            let id: i64 = row.get(0)?;
-            let questions: String = row.get(1)?;
-            let answers: String = row.get(2)?;
-            let doc_id: i64 = row.get(3)?;
-            let difficulty: f64 = row.get(4)?;
-            let interval: i64 = row.get(5)?;
-            let reps: i16 = row.get(6)?;
-            Ok(FlashCard::from_db(
-                &questions, &answers, id, doc_id, interval, reps, difficulty,
-            ))
-        })
-        .collect()
+        let cards: Vec<(i64, Stats)> = rows
+            .map(|row| {
+                let mut stats = Stats::new();
+                stats.difficultly = row.get(1)?;
+                stats.interval = row.get(2)?;
+                stats.num_reps = row.get(3)?;
+                let scheduled: Option<NaiveDate> = row.get(4)?;
+                stats.last_review = row
+                    .get::<_, Option<NaiveDate>>(5)?
+                    .or_else(|| scheduled.map(|date| date - Duration::days(stats.interval)));
+                stats.seed_fsrs();
+                Ok((row.get(0)?, stats))
+            })
+            .collect()?;
+
+        let mut seeded = 0;
+        for (id, stats) in cards.iter().filter(|(_, stats)| stats.stability > 0.0) {
+            let (sql, values) = Query::update()
+                .table(FlashCards::Table)
+                .values(vec![
+                    (FlashCards::Stability, stats.stability.into()),
+                    (FlashCards::RecallDifficulty, stats.recall_difficulty.into()),
+                    (FlashCards::Reviewed, stats.last_review.into()),
+                ])
+                .and_where(Expr::col(FlashCards::Id).eq(*id))
+                .build(SqliteQueryBuilder);
+
+            self.conn.execute(
+                sql.as_str(),
+                RusqliteValues::from(values).as_params().as_slice(),
+            )?;
+            seeded += 1;
+        }
+        Ok(seeded)
     }
 }
@@ -0,0 +1,651 @@
+//! The schema of the database, as a list of migrations. The version of a
+//! database is kept in its `user_version`, and the migrations it hasn't seen
+//! yet are run in order when it is opened.
+//!
+//! A migration is never changed once it is released, a change of the schema
+//! is a new migration at the end of the list.
+
+use std::{fmt, path::Path, path::PathBuf};
+
+use rusqlite::{Connection, DatabaseName, Transaction};
+use sea_query::{
+    Alias, ColumnDef, ForeignKey, ForeignKeyAction, Index, Query, SqliteQueryBuilder, Table, Value,
+};
+
+use super::{
+    documents::Documents, files::Files, flashcards::FlashCards, review_log::ReviewLog,
+    settings::Settings, Database,
+};
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// The migrations in order. A database at version `n` has run the first `n`.
+const MIGRATIONS: [Migration; 6] = [
+    create_tables,
+    index_due_cards,
+    forget_file_state,
+    drop_unique_titles,
+    create_settings,
+    add_drill_repeats,
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(rusqlite::Error),
+    /// The backup before the migration failed, so nothing was migrated.
+    Backup(PathBuf, rusqlite::Error),
+    /// The database was written by a newer version of the app.
+    Newer {
+        version: i64,
+        supported: i64,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(err) => write!(f, "cannot open the database: {}", err),
+            MigrationError::Backup(path, err) => {
+                write!(
+                    f,
+                    "cannot back up the database to {}: {}",
+                    path.display(),
+                    err
+                )
+            }
+            MigrationError::Newer { version, supported } => write!(
+                f,
+                "the database has schema version {}, but this version of the app only knows \
+                 up to {}. Update the app to open it",
+                version, supported
+            ),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Database(err)
+    }
+}
+
+impl Database {
+    /// The version of the schema that this version of the app writes.
+    pub fn schema_version() -> i64 {
+        MIGRATIONS.len() as i64
+    }
+
+    /// Runs the migrations that the database at `path` hasn't seen, each in
+    /// its own transaction. A database that holds any tables is backed up
+    /// next to it first, and a database of a newer version is refused.
+    pub fn migrate(conn: &mut Connection, path: &Path) -> Result<(), MigrationError> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let supported = Database::schema_version();
+        if version > supported {
+            return Err(MigrationError::Newer { version, supported });
+        }
+        if version == supported {
+            return Ok(());
+        }
+
+        let tables: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+            [],
+            |row| row.get(0),
+        )?;
+        if tables > 0 {
+            let backup = PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+            conn.backup(DatabaseName::Main, &backup, None)
+                .map_err(|err| MigrationError::Backup(backup.clone(), err))?;
+            // On stderr, so the output of the commands stays parseable.
+            eprintln!(
+                "Backed up the database to {} before migrating it",
+                backup.display()
+            );
+        }
+
+        // A migration that creates a table again drops the old one, which
+        // must not delete the rows that refer to it. The foreign keys can't be
+        // turned off inside a transaction, and are on again for the rest of
+        // the connection afterwards.
+        conn.pragma_update(None, "foreign_keys", false)?;
+        let result = Database::run_migrations(conn, version);
+        conn.pragma_update(None, "foreign_keys", true)?;
+        result
+    }
+
+    fn run_migrations(conn: &mut Connection, version: i64) -> Result<(), MigrationError> {
+        for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let tx = conn.transaction()?;
+            migration(&tx)?;
+            tx.pragma_update(None, "user_version", step as i64 + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the column unless the table has it already.
+fn add_column(tx: &Transaction, table: &str, column: &str, query: String) -> rusqlite::Result<()> {
+    let count: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if count == 0 {
+        tx.execute_batch(&query)?;
+    }
+    Ok(())
+}
+
+/// Version 1. Creates the tables. The databases from before the versions
+/// can lack some of the tables and columns, which are added to them.
+fn create_tables(tx: &Transaction) -> rusqlite::Result<()> {
+    let queries = [
+        Table::create()
+            .table(Files::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Files::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Files::Path).string().unique_key())
+            .col(ColumnDef::new(Files::Name).string())
+            .col(ColumnDef::new(Files::Hash).string())
+            .col(ColumnDef::new(Files::Modified).big_integer())
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(Documents::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Documents::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Documents::Title).string().unique_key())
+            .col(ColumnDef::new(Documents::Content).string())
+            .col(ColumnDef::new(Documents::File).integer())
+            .foreign_key(
+                ForeignKey::create()
+                    .from(Documents::Table, Documents::File)
+                    .to(Files::Table, Files::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .on_update(ForeignKeyAction::Cascade),
+            )
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(FlashCards::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(FlashCards::Id)
+                    .integer()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(FlashCards::Uid).string())
+            .col(ColumnDef::new(FlashCards::Questions).string().unique_key())
+            .col(ColumnDef::new(FlashCards::Answers).string())
+            .col(ColumnDef::new(FlashCards::DocId).integer())
+            .col(ColumnDef::new(FlashCards::Difficulty).float())
+            .col(ColumnDef::new(FlashCards::Interval).integer())
+            .col(ColumnDef::new(FlashCards::Reps).integer())
+            .col(ColumnDef::new(FlashCards::Created).date())
+            .col(ColumnDef::new(FlashCards::Scheduled).date())
+            .col(ColumnDef::new(FlashCards::Stability).float().default(0.0))
+            .col(
+                ColumnDef::new(FlashCards::RecallDifficulty)
+                    .float()
+                    .default(0.0),
+            )
+            .col(ColumnDef::new(FlashCards::Reviewed).date())
+            .col(ColumnDef::new(FlashCards::Retired).boolean().default(false))
+            .col(ColumnDef::new(FlashCards::Tags).string())
+            .foreign_key(
+                ForeignKey::create()
+                    .from(FlashCards::Table, FlashCards::DocId)
+                    .to(Documents::Table, Documents::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .on_update(ForeignKeyAction::Cascade),
+            )
+            .build(SqliteQueryBuilder),
+        Table::create()
+            .table(ReviewLog::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(ReviewLog::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(ReviewLog::CardId).integer())
+            .col(ColumnDef::new(ReviewLog::Reviewed).date_time())
+            .col(ColumnDef::new(ReviewLog::Grade).integer())
+            .col(ColumnDef::new(ReviewLog::PrevInterval).integer())
+            .col(ColumnDef::new(ReviewLog::NewInterval).integer())
+            .col(ColumnDef::new(ReviewLog::Ease).float())
+            .col(ColumnDef::new(ReviewLog::Duration).integer())
+            .foreign_key(
+                ForeignKey::create()
+                    .from(ReviewLog::Table, ReviewLog::CardId)
+                    .to(FlashCards::Table, FlashCards::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .on_update(ForeignKeyAction::Cascade),
+            )
+            .build(SqliteQueryBuilder),
+    ]
+    .join(";");
+    tx.execute_batch(&queries)?;
+
+    // `CREATE TABLE IF NOT EXISTS` leaves the tables of an older database
+    // untouched, the columns added to them later are appended here.
+    let alter = |table: FlashCards, column: &mut ColumnDef| {
+        Table::alter()
+            .table(table)
+            .add_column(column)
+            .build(SqliteQueryBuilder)
+    };
+    add_column(
+        tx,
+        "flashcards",
+        "uid",
+        alter(FlashCards::Table, ColumnDef::new(FlashCards::Uid).string()),
+    )?;
+    add_column(
+        tx,
+        "flashcards",
+        "stability",
+        alter(
+            FlashCards::Table,
+            ColumnDef::new(FlashCards::Stability).float().default(0.0),
+        ),
+    )?;
+    add_column(
+        tx,
+        "flashcards",
+        "recall_difficulty",
+        alter(
+            FlashCards::Table,
+            ColumnDef::new(FlashCards::RecallDifficulty)
+                .float()
+                .default(0.0),
+        ),
+    )?;
+    add_column(
+        tx,
+        "flashcards",
+        "reviewed_at",
+        alter(
+            FlashCards::Table,
+            ColumnDef::new(FlashCards::Reviewed).date(),
+        ),
+    )?;
+    add_column(
+        tx,
+        "flashcards",
+        "retired",
+        alter(
+            FlashCards::Table,
+            ColumnDef::new(FlashCards::Retired).boolean().default(false),
+        ),
+    )?;
+    add_column(
+        tx,
+        "flashcards",
+        "tags",
+        alter(FlashCards::Table, ColumnDef::new(FlashCards::Tags).string()),
+    )?;
+    add_column(
+        tx,
+        "files",
+        "hash",
+        Table::alter()
+            .table(Files::Table)
+            .add_column(ColumnDef::new(Files::Hash).string())
+            .build(SqliteQueryBuilder),
+    )?;
+    add_column(
+        tx,
+        "files",
+        "modified_at",
+        Table::alter()
+            .table(Files::Table)
+            .add_column(ColumnDef::new(Files::Modified).big_integer())
+            .build(SqliteQueryBuilder),
+    )?;
+    add_column(
+        tx,
+        "documents",
+        "file",
+        Table::alter()
+            .table(Documents::Table)
+            .add_column(ColumnDef::new(Documents::File).integer())
+            .build(SqliteQueryBuilder),
+    )?;
+
+    // A column with a unique constraint can't be added to an existing
+    // table, therefore the uniqueness of the ID is an index.
+    tx.execute_batch("CREATE UNIQUE INDEX IF NOT EXISTS flashcards_uid ON flashcards (uid)")
+}
+
+/// Version 2. Indexes the due queue and the answers of a card.
+fn index_due_cards(tx: &Transaction) -> rusqlite::Result<()> {
+    let queries = [
+        Index::create()
+            .name("flashcards_scheduled")
+            .table(FlashCards::Table)
+            .col(FlashCards::Scheduled)
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("review_log_card")
+            .table(ReviewLog::Table)
+            .col(ReviewLog::CardId)
+            .build(SqliteQueryBuilder),
+    ]
+    .join(";");
+    tx.execute_batch(&queries)
+}
+
+/// Version 3. The tags of the org cards are read since this version. The
+/// state of the imported files is forgotten, so the next import reads them
+/// again even when they didn't change, and their cards get their tags.
+fn forget_file_state(tx: &Transaction) -> rusqlite::Result<()> {
+    let sql = Query::update()
+        .table(Files::Table)
+        .values(vec![
+            (Files::Hash, Value::String(None)),
+            (Files::Modified, Value::BigInt(None)),
+        ])
+        .to_string(SqliteQueryBuilder);
+    tx.execute_batch(&sql)
+}
+
+/// Version 4. Documents are found by their file, and cards by their ID or
+/// their question within the document, so two files can have documents with
+/// the same title and cards with the same question. The tables are created
+/// again without the unique constraints, which SQLite can't drop.
+fn drop_unique_titles(tx: &Transaction) -> rusqlite::Result<()> {
+    let documents = Table::create()
+        .table(Alias::new("documents_new"))
+        .col(
+            ColumnDef::new(Documents::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Documents::Title).string())
+        .col(ColumnDef::new(Documents::Content).string())
+        .col(ColumnDef::new(Documents::File).integer())
+        .foreign_key(
+            ForeignKey::create()
+                .from(Alias::new("documents_new"), Documents::File)
+                .to(Files::Table, Files::Id)
+                .on_delete(ForeignKeyAction::SetNull)
+                .on_update(ForeignKeyAction::Cascade),
+        )
+        .build(SqliteQueryBuilder);
+    let flashcards = Table::create()
+        .table(Alias::new("flashcards_new"))
+        .col(
+            ColumnDef::new(FlashCards::Id)
+                .integer()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(FlashCards::Uid).string())
+        .col(ColumnDef::new(FlashCards::Questions).string())
+        .col(ColumnDef::new(FlashCards::Answers).string())
+        .col(ColumnDef::new(FlashCards::DocId).integer())
+        .col(ColumnDef::new(FlashCards::Difficulty).float())
+        .col(ColumnDef::new(FlashCards::Interval).integer())
+        .col(ColumnDef::new(FlashCards::Reps).integer())
+        .col(ColumnDef::new(FlashCards::Created).date())
+        .col(ColumnDef::new(FlashCards::Scheduled).date())
+        .col(ColumnDef::new(FlashCards::Stability).float().default(0.0))
+        .col(
+            ColumnDef::new(FlashCards::RecallDifficulty)
+                .float()
+                .default(0.0),
+        )
+        .col(ColumnDef::new(FlashCards::Reviewed).date())
+        .col(ColumnDef::new(FlashCards::Retired).boolean().default(false))
+        .col(ColumnDef::new(FlashCards::Tags).string())
+        .foreign_key(
+            ForeignKey::create()
+                .from(Alias::new("flashcards_new"), FlashCards::DocId)
+                .to(Documents::Table, Documents::Id)
+                .on_delete(ForeignKeyAction::Cascade)
+                .on_update(ForeignKeyAction::Cascade),
+        )
+        .build(SqliteQueryBuilder);
+    // The columns are named, as the columns added by `create_tables` to an
+    // older database come in another order.
+    let document_columns = || {
+        vec![
+            Documents::Id,
+            Documents::Title,
+            Documents::Content,
+            Documents::File,
+        ]
+    };
+    let card_columns = || {
+        vec![
+            FlashCards::Id,
+            FlashCards::Uid,
+            FlashCards::Questions,
+            FlashCards::Answers,
+            FlashCards::DocId,
+            FlashCards::Difficulty,
+            FlashCards::Interval,
+            FlashCards::Reps,
+            FlashCards::Created,
+            FlashCards::Scheduled,
+            FlashCards::Stability,
+            FlashCards::RecallDifficulty,
+            FlashCards::Reviewed,
+            FlashCards::Retired,
+            FlashCards::Tags,
+        ]
+    };
+    let copy_documents = Query::insert()
+        .into_table(Alias::new("documents_new"))
+        .columns(document_columns())
+        .select_from(
+            Query::select()
+                .columns(document_columns())
+                .from(Documents::Table)
+                .to_owned(),
+        )
+        .expect("the same columns are selected")
+        .to_string(SqliteQueryBuilder);
+    let copy_cards = Query::insert()
+        .into_table(Alias::new("flashcards_new"))
+        .columns(card_columns())
+        .select_from(
+            Query::select()
+                .columns(card_columns())
+                .from(FlashCards::Table)
+                .to_owned(),
+        )
+        .expect("the same columns are selected")
+        .to_string(SqliteQueryBuilder);
+
+    let queries = [
+        documents,
+        flashcards,
+        copy_documents,
+        copy_cards,
+        Table::drop()
+            .table(FlashCards::Table)
+            .build(SqliteQueryBuilder),
+        Table::drop()
+            .table(Documents::Table)
+            .build(SqliteQueryBuilder),
+        Table::rename()
+            .table(Alias::new("documents_new"), Documents::Table)
+            .build(SqliteQueryBuilder),
+        Table::rename()
+            .table(Alias::new("flashcards_new"), FlashCards::Table)
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("flashcards_uid")
+            .table(FlashCards::Table)
+            .col(FlashCards::Uid)
+            .unique()
+            .build(SqliteQueryBuilder),
+        Index::create()
+            .name("flashcards_scheduled")
+            .table(FlashCards::Table)
+            .col(FlashCards::Scheduled)
+            .build(SqliteQueryBuilder),
+    ]
+    .join(";");
+    tx.execute_batch(&queries)
+}
+
+/// Version 5. Keeps the settings that the command line and the server read.
+fn create_settings(tx: &Transaction) -> rusqlite::Result<()> {
+    let sql = Table::create()
+        .table(Settings::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Settings::Key)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Settings::Value).string())
+        .build(SqliteQueryBuilder);
+    tx.execute_batch(&sql)
+}
+
+/// Version 6. Keeps the number of times a card was drilled in org-drill
+/// before it was imported. The files are read again, so the cards imported
+/// from org-drill before get theirs.
+fn add_drill_repeats(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(
+        tx,
+        "flashcards",
+        "drill_repeats",
+        Table::alter()
+            .table(FlashCards::Table)
+            .add_column(
+                ColumnDef::new(FlashCards::DrillRepeats)
+                    .integer()
+                    .default(0),
+            )
+            .build(SqliteQueryBuilder),
+    )?;
+    forget_file_state(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// The tables as the versions before the migrations created them.
+    const LEGACY_SCHEMA: &str = "
+        CREATE TABLE files (id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+            path text UNIQUE, name text);
+        CREATE TABLE documents (id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+            title text UNIQUE, content text);
+        CREATE TABLE flashcards (id integer PRIMARY KEY AUTOINCREMENT,
+            questions text UNIQUE, answers text, document integer, difficulty real,
+            intervals integer, repetitions integer, created_at text, scheduled_at text,
+            FOREIGN KEY (document) REFERENCES documents (id)
+                ON DELETE CASCADE ON UPDATE CASCADE);
+        INSERT INTO documents (id, title, content) VALUES (1, 'Rust', 'A language.');
+        INSERT INTO flashcards (questions, answers, document, difficulty, intervals,
+            repetitions, created_at, scheduled_at)
+            VALUES ('What is Rust?', 'A language.', 1, 2.5, 4, 2, '2022-01-01', '2022-01-05');";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flashcards-{}-{}.db3", name, std::process::id()))
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migrate_legacy_database() {
+        let path = temp_path("legacy");
+        let backup = PathBuf::from(format!("{}.v0.bak", path.display()));
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LEGACY_SCHEMA).unwrap();
+        conn.pragma_update(None, "foreign_keys", true).unwrap();
+
+        Database::migrate(&mut conn, &path).unwrap();
+        assert_eq!(
+            count(&conn, "PRAGMA user_version"),
+            Database::schema_version()
+        );
+        assert!(backup.exists());
+        fs::remove_file(&backup).unwrap();
+
+        // The rebuilt tables kept the rows, and got the new columns.
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM flashcards WHERE document = 1 AND intervals = 4 \
+                 AND uid IS NULL AND retired = 0"
+            ),
+            1
+        );
+        // The titles and questions are no longer unique.
+        conn.execute_batch(
+            "INSERT INTO documents (title, content) VALUES ('Rust', 'Another file.');
+             INSERT INTO flashcards (questions, answers, document)
+                 VALUES ('What is Rust?', 'A game.', 2);",
+        )
+        .unwrap();
+
+        assert_eq!(count(&conn, "PRAGMA foreign_keys"), 1);
+        conn.execute_batch("DELETE FROM documents WHERE id = 1")
+            .unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM flashcards"), 1);
+    }
+
+    #[test]
+    fn migrate_new_database() {
+        let path = temp_path("new");
+        let mut conn = Connection::open_in_memory().unwrap();
+        Database::migrate(&mut conn, &path).unwrap();
+        assert_eq!(
+            count(&conn, "PRAGMA user_version"),
+            Database::schema_version()
+        );
+        // An empty database has nothing to back up.
+        assert!(!PathBuf::from(format!("{}.v0.bak", path.display())).exists());
+    }
+
+    #[test]
+    fn newer_database_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", Database::schema_version() + 1)
+            .unwrap();
+        assert!(matches!(
+            Database::migrate(&mut conn, &temp_path("newer")),
+            Err(MigrationError::Newer { .. })
+        ));
+    }
+
+    #[test]
+    fn foreign_keys_on_every_connection() {
+        let path = temp_path("connect");
+        drop(Database::connect(&path).unwrap());
+        // The second connection has nothing to migrate.
+        let db = Database::connect(&path).unwrap();
+        assert_eq!(count(&db.conn, "PRAGMA foreign_keys"), 1);
+        drop(db);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,18 @@
+mod backup;
 mod db;
+mod decks;
+mod migrations;
 mod flashcards;
 mod documents;
 mod files;
+mod review_log;
+mod settings;
+mod stats;
+mod sync;
 
+pub use backup::{inspect_backup, Backup, BackupSummary, DEFAULT_BACKUPS};
 pub use db::Database;
+pub use decks::{Deck, DeckKey};
+pub use flashcards::FlashCards;
+pub use migrations::MigrationError;
+pub use sync::SyncSummary;
@@ -0,0 +1,125 @@
+use fallible_iterator::FallibleIterator;
+use rusqlite::Result;
+use sea_query::{Expr, Iden, Order, Query, SqliteQueryBuilder};
+
+sea_query::sea_query_driver_rusqlite!();
+use sea_query_driver_rusqlite::RusqliteValues;
+
+use crate::sr::{Grade, Review};
+
+use super::Database;
+
+pub enum ReviewLog {
+    Table,
+    Id,
+    CardId,
+    Reviewed,
+    Grade,
+    PrevInterval,
+    NewInterval,
+    Ease,
+    Duration,
+}
+
+impl Iden for ReviewLog {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                ReviewLog::Table => "review_log",
+                ReviewLog::Id => "id",
+                ReviewLog::CardId => "card",
+                ReviewLog::Reviewed => "reviewed_at",
+                ReviewLog::Grade => "grade",
+                ReviewLog::PrevInterval => "previous_interval",
+                ReviewLog::NewInterval => "new_interval",
+                ReviewLog::Ease => "ease",
+                ReviewLog::Duration => "duration_ms",
+            }
+        )
+        .unwrap();
+    }
+}
+
+impl Database {
+    pub fn insert_reviews(&self, reviews: &Vec<Review>) -> Result<()> {
+        for review in reviews {
+            let (sql, values) = Query::insert()
+                .into_table(ReviewLog::Table)
+                .columns(vec![
+                    ReviewLog::CardId,
+                    ReviewLog::Reviewed,
+                    ReviewLog::Grade,
+                    ReviewLog::PrevInterval,
+                    ReviewLog::NewInterval,
+                    ReviewLog::Ease,
+                    ReviewLog::Duration,
+                ])
+                .values_panic(vec![
+                    review.card_id.into(),
+                    review.reviewed_at.into(),
+                    review.grade.quality().into(),
+                    review.previous_interval.into(),
+                    review.new_interval.into(),
+                    review.ease.into(),
+                    review.duration.into(),
+                ])
+                .build(SqliteQueryBuilder);
+
+            self.conn.execute(
+                sql.as_str(),
+                RusqliteValues::from(values).as_params().as_slice(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Loads the whole review history, ordered by card and then by time.
+    pub fn load_reviews(&self) -> Result<Vec<Review>> {
+        let (sql, values) = Query::select()
+            .columns(vec![
+                ReviewLog::CardId,
+                ReviewLog::Reviewed,
+                ReviewLog::Grade,
+                ReviewLog::PrevInterval,
+                ReviewLog::NewInterval,
+                ReviewLog::Ease,
+                ReviewLog::Duration,
+            ])
+            .from(ReviewLog::Table)
+            .order_by(ReviewLog::CardId, Order::Asc)
+            .order_by(ReviewLog::Reviewed, Order::Asc)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        rows.map(|row| {
+            Ok(Review {
+                card_id: row.get(0)?,
+                reviewed_at: row.get(1)?,
+                grade: Grade::from_quality(row.get(2)?),
+                previous_interval: row.get(3)?,
+                new_interval: row.get(4)?,
+                ease: row.get(5)?,
+                duration: row.get(6)?,
+            })
+        })
+        .collect()
+    }
+
+    /// Number of answers ever given for the card.
+    pub fn count_reviews(&self, card_id: i64) -> Result<i64> {
+        let (sql, values) = Query::select()
+            .expr(Expr::col(ReviewLog::Id).count())
+            .from(ReviewLog::Table)
+            .and_where(Expr::col(ReviewLog::CardId).eq(card_id))
+            .build(SqliteQueryBuilder);
+
+        self.conn.query_row(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+            |row| row.get(0),
+        )
+    }
+}
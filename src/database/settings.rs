@@ -0,0 +1,126 @@
+//! The settings of a profile that the command line and the server need as
+//! well. The window keeps its settings itself and writes these into the
+//! database of the profile whenever they change.
+
+use rusqlite::Result;
+use sea_query::{Expr, Iden, Query, SqliteQueryBuilder};
+use serde_json::Value;
+
+sea_query::sea_query_driver_rusqlite!();
+use sea_query_driver_rusqlite::RusqliteValues;
+
+use crate::sr::Algorithm;
+
+use super::{backup::DEFAULT_BACKUPS, Database};
+
+pub enum Settings {
+    Table,
+    Key,
+    Value,
+}
+
+impl Iden for Settings {
+    fn unquoted(&self, s: &mut dyn std::fmt::Write) {
+        write!(
+            s,
+            "{}",
+            match self {
+                Settings::Table => "settings",
+                Settings::Key => "key",
+                Settings::Value => "value",
+            }
+        )
+        .unwrap();
+    }
+}
+
+const BACKUPS: &str = "backups";
+const ALGORITHM: &str = "algorithm";
+const FSRS_WEIGHTS: &str = "fsrs_weights";
+
+impl Database {
+    /// The value of a setting, stored as JSON.
+    fn setting(&self, key: &str) -> Result<Option<Value>> {
+        let (sql, values) = Query::select()
+            .column(Settings::Value)
+            .from(Settings::Table)
+            .and_where(Expr::col(Settings::Key).eq(key))
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        match rows.next()? {
+            Some(row) => {
+                let text: String = row.get(0)?;
+                Ok(serde_json::from_str(&text).ok())
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set_setting(&self, key: &str, value: Value) -> Result<()> {
+        let (sql, values) = Query::update()
+            .table(Settings::Table)
+            .values(vec![(Settings::Value, value.to_string().into())])
+            .and_where(Expr::col(Settings::Key).eq(key))
+            .build(SqliteQueryBuilder);
+        let updated = self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        if updated > 0 {
+            return Ok(());
+        }
+
+        let (sql, values) = Query::insert()
+            .into_table(Settings::Table)
+            .columns(vec![Settings::Key, Settings::Value])
+            .values_panic(vec![key.into(), value.to_string().into()])
+            .build(SqliteQueryBuilder);
+        self.conn.execute(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// The number of snapshots that are kept, as set in the window.
+    pub fn backup_count(&self) -> usize {
+        self.setting(BACKUPS)
+            .ok()
+            .flatten()
+            .and_then(|value| value.as_u64())
+            .map_or(DEFAULT_BACKUPS, |count| count as usize)
+    }
+
+    pub fn set_backup_count(&self, count: usize) -> Result<()> {
+        self.set_setting(BACKUPS, Value::from(count))
+    }
+
+    /// The scheduler of the profile, if the window stored one.
+    pub fn algorithm(&self) -> Result<Option<Algorithm>> {
+        Ok(self
+            .setting(ALGORITHM)?
+            .and_then(|value| value.as_str().and_then(Algorithm::from_name)))
+    }
+
+    pub fn set_algorithm(&self, algorithm: Algorithm) -> Result<()> {
+        self.set_setting(ALGORITHM, Value::from(algorithm.name()))
+    }
+
+    /// The FSRS weights of the profile, if the window stored them.
+    pub fn fsrs_weights(&self) -> Result<Option<[f64; 17]>> {
+        let weights: Option<Vec<f64>> = self.setting(FSRS_WEIGHTS)?.and_then(|value| {
+            value
+                .as_array()?
+                .iter()
+                .map(|weight| weight.as_f64())
+                .collect()
+        });
+        Ok(weights.and_then(|weights| weights.try_into().ok()))
+    }
+
+    pub fn set_fsrs_weights(&self, weights: &[f64; 17]) -> Result<()> {
+        self.set_setting(FSRS_WEIGHTS, Value::from(weights.to_vec()))
+    }
+}
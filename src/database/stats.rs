@@ -0,0 +1,128 @@
+use chrono::{DateTime, Duration, Utc};
+use fallible_iterator::FallibleIterator;
+use rusqlite::Result;
+use sea_query::{Condition, Expr, Order, Query, SelectStatement, SqliteQueryBuilder};
+
+sea_query::sea_query_driver_rusqlite!();
+use sea_query_driver_rusqlite::RusqliteValues;
+
+use super::{documents::Documents, flashcards::FlashCards, review_log::ReviewLog, Database};
+
+/// Counts over the whole collection.
+#[derive(Debug, Default)]
+pub struct CollectionStats {
+    pub documents: i64,
+    pub cards: i64,
+    /// Cards that were never answered.
+    pub new_cards: i64,
+    pub due: i64,
+    pub retired: i64,
+    pub reviews: i64,
+    pub reviews_today: i64,
+    /// Share of the answers of the last 30 days that were correct.
+    pub retention: Option<f64>,
+}
+
+impl Database {
+    fn count(&self, query: &mut SelectStatement) -> Result<i64> {
+        let (sql, values) = query.build(SqliteQueryBuilder);
+        self.conn.query_row(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+            |row| row.get(0),
+        )
+    }
+
+    fn count_cards(&self, condition: Condition) -> Result<i64> {
+        self.count(
+            Query::select()
+                .expr(Expr::col(FlashCards::Id).count())
+                .from(FlashCards::Table)
+                .cond_where(condition),
+        )
+    }
+
+    fn count_answers(&self, condition: Condition) -> Result<i64> {
+        self.count(
+            Query::select()
+                .expr(Expr::col(ReviewLog::Id).count())
+                .from(ReviewLog::Table)
+                .cond_where(condition),
+        )
+    }
+
+    pub fn collection_stats(&self) -> Result<CollectionStats> {
+        let now = Utc::now();
+        let today = now.date().naive_local();
+        let active = || Condition::all().add(Expr::col(FlashCards::Retired).eq(false));
+        let month =
+            Condition::all().add(Expr::col(ReviewLog::Reviewed).gte(now - Duration::days(30)));
+        let answered = self.count_answers(month.clone())?;
+        let correct = self.count_answers(month.add(Expr::col(ReviewLog::Grade).gte(3)))?;
+
+        Ok(CollectionStats {
+            documents: self.count(
+                Query::select()
+                    .expr(Expr::col(Documents::Id).count())
+                    .from(Documents::Table),
+            )?,
+            cards: self.count_cards(active())?,
+            new_cards: self.count_cards(
+                active()
+                    .add(Expr::col(FlashCards::Reviewed).is_null())
+                    .add(Expr::col(FlashCards::Interval).eq(0)),
+            )?,
+            due: self.count_cards(active().add(Expr::col(FlashCards::Scheduled).lte(today)))?,
+            retired: self
+                .count_cards(Condition::all().add(Expr::col(FlashCards::Retired).eq(true)))?,
+            reviews: self.count_answers(Condition::all())?,
+            reviews_today: self.count_answers(
+                Condition::all().add(Expr::col(ReviewLog::Reviewed).gte(today.and_hms(0, 0, 0))),
+            )?,
+            retention: if answered > 0 {
+                Some(correct as f64 / answered as f64)
+            } else {
+                None
+            },
+        })
+    }
+
+    /// The number of due cards of every document that has any, ordered by
+    /// title.
+    pub fn due_counts(&self) -> Result<Vec<(String, i64)>> {
+        let (sql, values) = Query::select()
+            .column((Documents::Table, Documents::Title))
+            .expr(Expr::tbl(FlashCards::Table, FlashCards::Id).count())
+            .from(FlashCards::Table)
+            .inner_join(
+                Documents::Table,
+                Expr::tbl(FlashCards::Table, FlashCards::DocId)
+                    .equals(Documents::Table, Documents::Id),
+            )
+            .and_where(
+                Expr::tbl(FlashCards::Table, FlashCards::Scheduled)
+                    .lte(Utc::now().date().naive_local()),
+            )
+            .and_where(Expr::tbl(FlashCards::Table, FlashCards::Retired).eq(false))
+            .group_by_col((Documents::Table, Documents::Id))
+            .order_by((Documents::Table, Documents::Title), Order::Asc)
+            .build(SqliteQueryBuilder);
+
+        let mut stmt = self.conn.prepare(sql.as_str())?;
+        let rows = stmt.query(RusqliteValues::from(values).as_params().as_slice())?;
+        rows.map(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+    }
+
+    /// The time of the latest answer, if any card was ever answered.
+    pub fn last_review(&self) -> Result<Option<DateTime<Utc>>> {
+        let (sql, values) = Query::select()
+            .expr(Expr::col(ReviewLog::Reviewed).max())
+            .from(ReviewLog::Table)
+            .build(SqliteQueryBuilder);
+        self.conn.query_row(
+            sql.as_str(),
+            RusqliteValues::from(values).as_params().as_slice(),
+            |row| row.get(0),
+        )
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+
+use rusqlite::Result;
+
+use crate::org::Document;
+
+use super::flashcards::FlashCards;
+use super::Database;
+
+/// What a sync pass changed for a file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SyncSummary {
+    pub added: usize,
+    pub changed: usize,
+    pub retired: usize,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} retired",
+            self.added, self.changed, self.retired
+        )
+    }
+}
+
+impl Database {
+    /// Brings the database in line with the document parsed from a file.
+    /// Changed cards keep their scheduling stats, new cards are inserted and
+    /// the cards that are gone from the file are retired. The whole pass is
+    /// one transaction, so a failure leaves the document as it was.
+    pub fn sync_document(&self, file_id: i64, document: &mut Document) -> Result<SyncSummary> {
+        let tx = self.conn.unchecked_transaction()?;
+        let summary = self.sync_cards(file_id, document)?;
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    fn sync_cards(&self, file_id: i64, document: &mut Document) -> Result<SyncSummary> {
+        let mut summary = SyncSummary::default();
+        let doc_id = match self.find_document(file_id, document.get_title())? {
+            Some(id) => {
+                self.update_document(id, document.get_contents(), document.get_title(), file_id)?;
+                id
+            }
+            None if document.get_cards().is_empty() => return Ok(summary),
+            None => {
+                self.insert_documents(document.get_contents(), document.get_title(), file_id)?
+            }
+        };
+        document.update_id(doc_id);
+
+        let mut seen = vec![];
+        for card in document.get_cards() {
+            // The `:ID:` identifies a card. Cards stored without one, that
+            // got one just now, are found by their question in the document.
+            // Cards with the same question are matched in order.
+            let stored = match card.get_uid() {
+                Some(uid) => self.find_flashcard(FlashCards::Uid, uid)?,
+                None => None,
+            };
+            let stored = match stored {
+                Some(stored) => Some(stored),
+                None => self.find_document_flashcard(doc_id, card.get_questions(), &seen)?,
+            };
+            match stored {
+                Some(stored) => {
+                    let text_changed = stored.retired
+                        || stored.doc_id != doc_id
+                        || stored.uid.as_deref() != card.get_uid()
+                        || stored.questions != card.get_questions()
+                        || stored.answers != card.get_answers();
+                    let tags_changed = stored.tags != card.get_tags();
+                    if text_changed {
+                        self.update_card_text(stored.id, card)?;
+                    }
+                    if tags_changed {
+                        self.update_card_tags(stored.id, card.get_tags())?;
+                    }
+                    if text_changed || tags_changed {
+                        summary.changed += 1;
+                    }
+                    // The org-drill count written back into a file holds the
+                    // answers of the review log as well.
+                    let drill_repeats = match card.get_drill_repeats() {
+                        0 => 0,
+                        repeats => (repeats - self.count_reviews(stored.id)?).max(0),
+                    };
+                    if drill_repeats != stored.drill_repeats {
+                        self.update_drill_repeats(stored.id, drill_repeats)?;
+                    }
+                    seen.push(stored.id);
+                }
+                None => {
+                    seen.push(self.insert_flashcard(card)?);
+                    summary.added += 1;
+                }
+            }
+        }
+
+        for id in self.active_flashcards(doc_id)? {
+            if !seen.contains(&id) {
+                self.retire_flashcard(id)?;
+                summary.retired += 1;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::import::parse;
+
+    const NOTES: &str = "* Rust :context:
+** What is Rust? :card:
+*** A language
+** Who made it? :card:
+*** Graydon Hoare
+";
+
+    fn database() -> Database {
+        let mut conn = Connection::open_in_memory().unwrap();
+        Database::migrate(&mut conn, Path::new("test.db3")).unwrap();
+        conn.execute_batch("INSERT INTO files (id, path, name) VALUES (1, 'rust.org', 'rust.org')")
+            .unwrap();
+        Database { conn }
+    }
+
+    fn sync(db: &Database, text: &str) -> (usize, usize, usize) {
+        let summary = db.sync_document(1, &mut parse(text)).unwrap();
+        (summary.added, summary.changed, summary.retired)
+    }
+
+    fn cards(db: &Database) -> Vec<(i64, String, String, bool)> {
+        let mut stmt = db
+            .conn
+            .prepare("SELECT id, questions, answers, retired FROM flashcards ORDER BY id")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn add_change_retire() {
+        let db = database();
+        assert_eq!(sync(&db, NOTES), (2, 0, 0));
+        assert_eq!(sync(&db, NOTES), (0, 0, 0));
+
+        let edited = "* Rust :context:
+** What is Rust? :card:
+*** A programming language
+** Where was it made? :card:
+*** Mozilla
+";
+        assert_eq!(sync(&db, edited), (1, 1, 1));
+        let stored = cards(&db);
+        assert_eq!(stored.len(), 3);
+        assert_eq!(stored[0].2, "A programming language");
+        assert!(stored[1].3, "the removed card is retired");
+        assert_eq!(stored[2].1, "Where was it made?");
+
+        // A card that comes back is found again with its history.
+        assert_eq!(sync(&db, NOTES), (0, 2, 1));
+        let stored = cards(&db);
+        assert_eq!(stored.len(), 3);
+        assert!(!stored[1].3);
+        assert!(stored[2].3);
+    }
+
+    #[test]
+    fn same_questions_are_kept_apart() {
+        let notes = "* Words :context:
+** What does it mean? :card:
+*** Hello
+** What does it mean? :card:
+*** Goodbye
+";
+        let db = database();
+        assert_eq!(sync(&db, notes), (2, 0, 0));
+        assert_eq!(sync(&db, notes), (0, 0, 0));
+        let answers: Vec<String> = cards(&db).into_iter().map(|card| card.2).collect();
+        assert_eq!(answers, ["Hello", "Goodbye"]);
+
+        let one = "* Words :context:
+** What does it mean? :card:
+*** Hello
+";
+        assert_eq!(sync(&db, one), (0, 0, 1));
+    }
+
+    #[test]
+    fn ids_identify_cards() {
+        let db = database();
+        let notes = "* Rust :context:
+** What is Rust? :card:
+:PROPERTIES:
+:ID: rust
+:END:
+*** A language
+";
+        assert_eq!(sync(&db, notes), (1, 0, 0));
+        let renamed = notes.replace("What is Rust?", "What is the Rust language?");
+        assert_eq!(sync(&db, &renamed), (0, 1, 0));
+        let stored = cards(&db);
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].1, "What is the Rust language?");
+    }
+
+    #[test]
+    fn summary_display() {
+        let summary = SyncSummary {
+            added: 2,
+            changed: 1,
+            retired: 0,
+        };
+        assert_eq!(summary.to_string(), "2 added, 1 changed, 0 retired");
+    }
+}
@@ -0,0 +1,447 @@
+//! Compatibility with org-drill. The scheduling state of a card is kept in
+//! the property drawer of its headline, under the names org-drill uses, so
+//! the org files themselves are a record of the progress.
+
+use std::{cmp::Reverse, collections::HashMap, fs, path::Path};
+
+use chrono::{Duration, NaiveDate, Utc};
+use orgize::elements::Timestamp;
+
+use crate::{
+    cloze, database::Database, files, import::ImportError, org::FlashCard, properties, sr::Stats,
+};
+
+pub const CARD_TYPE: &str = "DRILL_CARD_TYPE";
+pub const LAST_INTERVAL: &str = "DRILL_LAST_INTERVAL";
+pub const REPEATS_SINCE_FAIL: &str = "DRILL_REPEATS_SINCE_FAIL";
+pub const TOTAL_REPEATS: &str = "DRILL_TOTAL_REPEATS";
+pub const EASE: &str = "DRILL_EASE";
+pub const LAST_REVIEWED: &str = "DRILL_LAST_REVIEWED";
+
+/// The native kind of card an org-drill card type maps to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CardKind {
+    Basic,
+    Cloze,
+}
+
+impl CardKind {
+    /// `simple` and `twosided` cards have their answers below the question,
+    /// like the basic cards. `hide1cloze` and the other cloze types become
+    /// cloze cards.
+    pub fn from_card_type(card_type: Option<&str>) -> Self {
+        match card_type {
+            Some(card_type) if card_type.contains("cloze") => CardKind::Cloze,
+            _ => CardKind::Basic,
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim().trim_start_matches(['[', '<']);
+    NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// The date of an org timestamp.
+pub fn timestamp_date(timestamp: &Timestamp) -> Option<NaiveDate> {
+    let start = match timestamp {
+        Timestamp::Active { start, .. }
+        | Timestamp::Inactive { start, .. }
+        | Timestamp::ActiveRange { start, .. }
+        | Timestamp::InactiveRange { start, .. } => start,
+        Timestamp::Diary { .. } => return None,
+    };
+    NaiveDate::from_ymd_opt(start.year as i32, start.month as u32, start.day as u32)
+}
+
+/// Reads the state org-drill keeps in the properties of a card, with the
+/// number of times it was drilled in total. Returns `None` if the card was
+/// never drilled.
+pub fn read_stats<F>(property: F) -> Option<(Stats, i64)>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let interval = property(LAST_INTERVAL).and_then(|value| value.parse::<f64>().ok());
+    let total = property(TOTAL_REPEATS).and_then(|value| value.parse::<i64>().ok());
+    if interval.is_none() && total.is_none() {
+        return None;
+    }
+
+    let mut stats = Stats::new();
+    stats.interval = interval.unwrap_or(0.0).round() as i64;
+    stats.num_reps = property(REPEATS_SINCE_FAIL)
+        .and_then(|value| value.parse().ok())
+        .or_else(|| total.and_then(|total| i16::try_from(total).ok()))
+        .unwrap_or(if stats.interval > 0 { 1 } else { 0 });
+    if let Some(ease) = property(EASE).and_then(|value| value.parse::<f64>().ok()) {
+        stats.difficultly = ease.max(1.3);
+    }
+    stats.last_review = property(LAST_REVIEWED).and_then(|value| parse_date(&value));
+    let total = total.unwrap_or(i64::from(stats.num_reps));
+    Some((stats, total))
+}
+
+/// Checks if the brackets hold something else than a cloze, like a checkbox,
+/// a timestamp or a statistics cookie.
+fn is_cloze(inner: &str) -> bool {
+    let inner = inner.trim();
+    !(inner.is_empty()
+        || matches!(inner, "X" | "x" | "-" | "...")
+        || parse_date(inner).is_some()
+        || (inner.ends_with('%') && inner[..inner.len() - 1].chars().all(|c| c.is_ascii_digit()))
+        || (inner.contains('/')
+            && inner
+                .split('/')
+                .all(|n| n.chars().all(|c| c.is_ascii_digit()))))
+}
+
+/// Converts the `[text]` and `[text||hint]` clozes of org-drill into
+/// `{{cN::text}}` deletions. Every cloze gets its own number, so they are
+/// hidden one at a time. Links are kept as they are.
+pub fn convert_clozes(text: &str) -> String {
+    let mut result = String::new();
+    let mut number = 0;
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        result += &rest[..start];
+        let after = &rest[start..];
+        if after.starts_with("[[") {
+            let end = after.find("]]").map(|i| i + 2).unwrap_or(2);
+            result += &after[..end];
+            rest = &after[end..];
+            continue;
+        }
+        let end = after[1..]
+            .find([']', '[', '\n'])
+            .map(|i| i + 1)
+            .filter(|i| after[*i..].starts_with(']'));
+        match end {
+            Some(end) if is_cloze(&after[1..end]) => {
+                number += 1;
+                let (answer, hint) = match after[1..end].split_once("||") {
+                    Some((answer, hint)) => (answer, Some(hint)),
+                    None => (&after[1..end], None),
+                };
+                result += &format!("{{{{c{}::{}", number, answer);
+                if let Some(hint) = hint {
+                    result += "::";
+                    result += hint;
+                }
+                result += "}}";
+                rest = &after[end + 1..];
+            }
+            _ => {
+                result += "[";
+                rest = &after[1..];
+            }
+        }
+    }
+    result + rest
+}
+
+/// The scheduling state of a card as it is written to the file.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub interval: i64,
+    pub repeats: i16,
+    pub total_repeats: i64,
+    pub ease: f64,
+    pub last_review: NaiveDate,
+    pub due: NaiveDate,
+}
+
+impl Schedule {
+    pub fn new(stats: &Stats, total_repeats: i64) -> Self {
+        let last_review = stats
+            .last_review
+            .unwrap_or_else(|| Utc::now().date().naive_local());
+        Self {
+            interval: stats.interval,
+            repeats: stats.num_reps,
+            total_repeats,
+            ease: stats.difficultly,
+            last_review,
+            due: last_review + Duration::days(stats.interval),
+        }
+    }
+}
+
+/// A card to write the schedule of. It is found by its `:ID:`, or by the
+/// title of its headline if it has none.
+pub struct Entry<'a> {
+    pub uid: Option<&'a str>,
+    pub question: &'a str,
+    pub schedule: Schedule,
+}
+
+/// The cards of a cloze headline have the `:ID:` of the headline with a
+/// `-c<number>` suffix.
+fn headline_uid(uid: &str) -> Option<&str> {
+    let (base, number) = uid.rsplit_once("-c")?;
+    if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+        Some(base)
+    } else {
+        None
+    }
+}
+
+/// The questions of the cards of the headline at `idx`, like the import reads
+/// them. A cloze headline has a card for every deletion or gap.
+fn headline_questions(lines: &[String], idx: usize) -> Vec<String> {
+    let title = match properties::headline_title(&lines[idx]) {
+        Some(title) => title,
+        None => return vec![],
+    };
+    let clozes = if cloze::has_cloze(title) {
+        cloze::expand(title)
+    } else {
+        let card_type = properties::get_property(lines, idx, CARD_TYPE);
+        let mut body = properties::section(lines, idx);
+        if CardKind::from_card_type(card_type.as_deref()) == CardKind::Cloze {
+            body = convert_clozes(&body);
+        }
+        if cloze::has_cloze(&body) {
+            cloze::expand(body.trim())
+        } else if properties::is_tagged_headline(&lines[idx], "drill") {
+            // The text below an org-drill card is its question, and the
+            // headlines below it are not answers.
+            let question = body.trim();
+            return vec![if question.is_empty() { title } else { question }.to_owned()];
+        } else {
+            let answers: Vec<String> = properties::subheadline_titles(lines, idx)
+                .into_iter()
+                .map(|answer| answer.to_owned())
+                .collect();
+            cloze::expand_gaps(title, &answers)
+        }
+    };
+    if clozes.is_empty() {
+        vec![title.to_owned()]
+    } else {
+        clozes
+            .into_iter()
+            .map(|(_, question, _)| question)
+            .collect()
+    }
+}
+
+fn find_card(lines: &[String], entry: &Entry) -> Option<usize> {
+    let cards: Vec<usize> = (0..lines.len())
+        .filter(|idx| properties::is_card_headline(&lines[*idx]))
+        .collect();
+    let by_id = |uid: &str| {
+        cards
+            .iter()
+            .copied()
+            .find(|idx| properties::get_property(lines, *idx, "ID").as_deref() == Some(uid))
+    };
+    match entry.uid {
+        Some(uid) => by_id(uid).or_else(|| headline_uid(uid).and_then(by_id)),
+        None => cards.iter().copied().find(|idx| {
+            headline_questions(lines, *idx)
+                .iter()
+                .any(|question| question.trim() == entry.question.trim())
+        }),
+    }
+}
+
+/// Writes the schedules into the headlines of the cards. Returns the new
+/// text, or `None` if nothing changed.
+///
+/// The cloze cards of a headline share its drawer, the one that is due first
+/// is written.
+pub fn write_schedule(text: &str, entries: &mut [Entry]) -> Option<String> {
+    let mut lines: Vec<String> = text.lines().map(|l| l.to_owned()).collect();
+    entries.sort_by_key(|entry| Reverse(entry.schedule.due));
+    for entry in entries.iter() {
+        let idx = match find_card(&lines, entry) {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let schedule = &entry.schedule;
+        properties::set_scheduled(
+            &mut lines,
+            idx,
+            &schedule.due.format("<%Y-%m-%d %a>").to_string(),
+        );
+        properties::set_property(
+            &mut lines,
+            idx,
+            LAST_INTERVAL,
+            &schedule.interval.to_string(),
+        );
+        properties::set_property(
+            &mut lines,
+            idx,
+            REPEATS_SINCE_FAIL,
+            &schedule.repeats.to_string(),
+        );
+        properties::set_property(
+            &mut lines,
+            idx,
+            TOTAL_REPEATS,
+            &schedule.total_repeats.to_string(),
+        );
+        properties::set_property(&mut lines, idx, EASE, &format!("{:.2}", schedule.ease));
+        properties::set_property(
+            &mut lines,
+            idx,
+            LAST_REVIEWED,
+            &schedule.last_review.format("[%Y-%m-%d %a]").to_string(),
+        );
+    }
+
+    let mut result = lines.join("\n");
+    if text.ends_with('\n') {
+        result += "\n";
+    }
+    if result == text {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// Writes the schedules of the cards back into the files they were imported
+/// from. Returns the number of files that changed.
+pub fn write_back(db: &Database, cards: &[&FlashCard]) -> Result<usize, ImportError> {
+    let mut by_document: HashMap<i64, Vec<&FlashCard>> = HashMap::new();
+    for card in cards {
+        by_document.entry(card.get_doc_id()).or_default().push(card);
+    }
+
+    let mut written = 0;
+    for (doc_id, cards) in by_document {
+        let (file_id, path) = match db.document_file(doc_id)? {
+            Some(file) => file,
+            None => continue,
+        };
+        let mut entries = vec![];
+        for card in cards {
+            // The repeats counted by org-drill before the import go on.
+            let total_repeats = card.get_drill_repeats() + db.count_reviews(card.get_id())?;
+            entries.push(Entry {
+                uid: card.get_uid(),
+                question: card.get_questions(),
+                schedule: Schedule::new(card.get_stats(), total_repeats),
+            });
+        }
+
+        let path = Path::new(&path);
+        let contents = fs::read_to_string(path)?;
+        if let Some(updated) = write_schedule(&contents, &mut entries) {
+            fs::write(path, &updated)?;
+            // The cards didn't change, so the next import can skip the file.
+            db.update_file_state(
+                file_id,
+                &files::content_hash(&updated),
+                files::modified_time(path)?,
+            )?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRILLED: &str = "* French
+** Greeting :drill:
+SCHEDULED: <2022-03-10 Thu>
+:PROPERTIES:
+:ID: greeting
+:DRILL_LAST_INTERVAL: 12.3
+:DRILL_REPEATS_SINCE_FAIL: 4
+:DRILL_TOTAL_REPEATS: 20
+:DRILL_EASE: 2.46
+:DRILL_LAST_REVIEWED: [2022-02-26 Sat 10:12]
+:END:
+Translate /hello/.
+*** Answer
+bonjour
+";
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_owned()).collect()
+    }
+
+    /// A card that was never drilled.
+    fn lines_without_drill() -> Vec<String> {
+        lines("* French\n** Greeting :drill:\nTranslate /hello/.\n")
+    }
+
+    #[test]
+    fn read_drilled_stats() {
+        let drilled = lines(DRILLED);
+        let (stats, total) = read_stats(|key| properties::get_property(&drilled, 1, key)).unwrap();
+        assert_eq!(stats.interval, 12);
+        assert_eq!(stats.num_reps, 4);
+        assert_eq!(stats.difficultly, 2.46);
+        assert_eq!(stats.last_review, Some(NaiveDate::from_ymd(2022, 2, 26)));
+        assert_eq!(total, 20);
+
+        let new = lines_without_drill();
+        assert!(read_stats(|key| properties::get_property(&new, 1, key)).is_none());
+    }
+
+    #[test]
+    fn write_schedule_keeps_total_repeats() {
+        let drilled = lines(DRILLED);
+        let (mut stats, total) =
+            read_stats(|key| properties::get_property(&drilled, 1, key)).unwrap();
+        stats.interval = 30;
+        stats.num_reps = 5;
+        stats.last_review = Some(NaiveDate::from_ymd(2022, 3, 10));
+        // One answer in the review log since the import.
+        let mut entries = [Entry {
+            uid: Some("greeting"),
+            question: "Translate /hello/.",
+            schedule: Schedule::new(&stats, total + 1),
+        }];
+
+        let text = write_schedule(DRILLED, &mut entries).unwrap();
+        let written = lines(&text);
+        let property = |key: &str| properties::get_property(&written, 1, key);
+        assert_eq!(property(TOTAL_REPEATS).as_deref(), Some("21"));
+        assert_eq!(property(REPEATS_SINCE_FAIL).as_deref(), Some("5"));
+        assert_eq!(property(LAST_INTERVAL).as_deref(), Some("30"));
+        assert_eq!(written[2], "SCHEDULED: <2022-04-09 Sat>");
+        // Read again, the schedule goes on from the written one.
+        let (stats, total) = read_stats(property).unwrap();
+        assert_eq!((stats.interval, stats.num_reps, total), (30, 5, 21));
+        // Nothing changes when the same schedule is written again.
+        assert!(write_schedule(&text, &mut entries).is_none());
+    }
+
+    #[test]
+    fn write_schedule_finds_cards_by_question() {
+        let mut entries = [Entry {
+            uid: None,
+            question: "Translate /hello/.",
+            schedule: Schedule::new(&Stats::new(), 1),
+        }];
+        let text = write_schedule(&lines_without_drill().join("\n"), &mut entries).unwrap();
+        assert!(text.contains(":DRILL_TOTAL_REPEATS: 1"));
+
+        let mut entries = [Entry {
+            uid: None,
+            question: "Greeting",
+            schedule: Schedule::new(&Stats::new(), 1),
+        }];
+        assert!(write_schedule(&lines_without_drill().join("\n"), &mut entries).is_none());
+    }
+
+    #[test]
+    fn convert_drill_clozes() {
+        assert_eq!(
+            convert_clozes("The capital of [France] is [Paris||city], see [[https://x.org][x]]"),
+            "The capital of {{c1::France}} is {{c2::Paris::city}}, see [[https://x.org][x]]"
+        );
+        assert_eq!(
+            convert_clozes("- [X] done [2022-01-01] [50%] [1/2]"),
+            "- [X] done [2022-01-01] [50%] [1/2]"
+        );
+    }
+}
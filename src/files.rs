@@ -1,6 +1,7 @@
 use std::{
     fs,
     path::Path,
+    time::UNIX_EPOCH,
 };
 
 use rusqlite::Row;
@@ -52,6 +53,27 @@ impl File {
     }
 }
 
+/// A FNV-1a hash of the contents. It is stable between builds, unlike the
+/// hasher of the standard library.
+pub fn content_hash(contents: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in contents.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Modification time of the file in nanoseconds since the epoch. Seconds are
+/// too coarse, a file can be saved twice within one.
+pub fn modified_time(path: &Path) -> std::io::Result<i64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as i64)
+        .unwrap_or(0))
+}
+
 impl From<&Row<'_>> for File {
     fn from(row: &Row) -> Self {
         Self {
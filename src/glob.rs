@@ -0,0 +1,153 @@
+//! Glob patterns to select the files of a folder import.
+//!
+//! `*` matches within a path component, `**` matches any number of
+//! directories, `?` matches a single character and `[a-z]` or `[!a-z]` a
+//! character class. A pattern without a `/` is matched against the file name
+//! only, so `*.org` selects org files at any depth.
+
+use std::path::Path;
+
+/// Splits a comma separated list of patterns.
+pub fn split(patterns: &str) -> Vec<&str> {
+    patterns
+        .split(',')
+        .map(|pattern| pattern.trim())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
+
+/// Checks if the path, relative to the imported folder, matches the pattern.
+pub fn matches_path(pattern: &str, relative: &Path) -> bool {
+    let components: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let text = if pattern.contains('/') {
+        components.join("/")
+    } else {
+        match components.last() {
+            Some(name) => name.clone(),
+            None => return false,
+        }
+    };
+    matches(pattern.trim_start_matches('/'), &text)
+}
+
+/// Checks if the path matches any of the patterns.
+pub fn matches_any(patterns: &[&str], relative: &Path) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| matches_path(pattern, relative))
+}
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_at(&pattern, &text)
+}
+
+fn match_at(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = &p[2..];
+            if rest.first() == Some(&'/') {
+                // `**/` also matches no directory at all.
+                let rest = &rest[1..];
+                match_at(rest, t)
+                    || (0..t.len()).any(|i| t[i] == '/' && match_at(rest, &t[i + 1..]))
+            } else {
+                (0..=t.len()).any(|i| match_at(rest, &t[i..]))
+            }
+        }
+        Some('*') => (0..=t.len())
+            .take_while(|i| *i == 0 || t[i - 1] != '/')
+            .any(|i| match_at(&p[1..], &t[i..])),
+        Some('?') => !t.is_empty() && t[0] != '/' && match_at(&p[1..], &t[1..]),
+        Some('[') => match class(p) {
+            Some((matched, len)) => {
+                !t.is_empty() && t[0] != '/' && matched(t[0]) && match_at(&p[len..], &t[1..])
+            }
+            // Without a closing bracket it is a plain character.
+            None => t.first() == Some(&'[') && match_at(&p[1..], &t[1..]),
+        },
+        Some(c) => t.first() == Some(c) && match_at(&p[1..], &t[1..]),
+    }
+}
+
+/// Parses the character class at the start of the pattern. Returns the
+/// matcher and the length of the class in the pattern.
+fn class(p: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let mut idx = 1;
+    let negated = matches!(p.get(idx), Some('!') | Some('^'));
+    if negated {
+        idx += 1;
+    }
+    let start = idx;
+    // A `]` right after the opening bracket is part of the class.
+    if p.get(idx) == Some(&']') {
+        idx += 1;
+    }
+    while *p.get(idx)? != ']' {
+        idx += 1;
+    }
+    let set = &p[start..idx];
+    let matched = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                found |= set[i] <= c && c <= set[i + 2];
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matched, idx + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_star_matches_any_depth() {
+        assert!(matches("**/*.org", "notes.org"));
+        assert!(matches("**/*.org", "a/b/notes.org"));
+        assert!(matches("daily/**", "daily/2022/01.org"));
+        assert!(matches("a/**/z.org", "a/z.org"));
+        assert!(matches("a/**/z.org", "a/b/c/z.org"));
+        assert!(!matches("a/**/z.org", "b/a/z.org"));
+    }
+
+    #[test]
+    fn single_star_stays_in_component() {
+        assert!(matches("*.org", "notes.org"));
+        assert!(!matches("*.org", "a/notes.org"));
+        assert!(matches("a/*/z.org", "a/b/z.org"));
+        assert!(!matches("a/*/z.org", "a/b/c/z.org"));
+    }
+
+    #[test]
+    fn classes_and_single_characters() {
+        assert!(matches("v?.org", "v1.org"));
+        assert!(!matches("v?.org", "v/.org"));
+        assert!(matches("[a-c]*.md", "beta.md"));
+        assert!(!matches("[!a-c]*.md", "beta.md"));
+        assert!(matches("[x.org", "[x.org"));
+    }
+
+    #[test]
+    fn pattern_without_slash_matches_the_name() {
+        assert!(matches_path("*.org", Path::new("deep/in/notes.org")));
+        assert!(!matches_path("deep/*.org", Path::new("deep/in/notes.org")));
+        assert!(matches_path("/deep/**", Path::new("deep/in/notes.org")));
+        assert!(matches_any(
+            &split("*.md, archive/**"),
+            Path::new("archive/old.org")
+        ));
+    }
+}
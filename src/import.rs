@@ -0,0 +1,211 @@
+//! Imports org and markdown files into the database.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use orgize::Org;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    anki, csv,
+    database::{Database, SyncSummary},
+    files, glob, markdown,
+    org::Document,
+    properties,
+};
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "cannot read the file: {}", err),
+            ImportError::Database(err) => write!(f, "cannot update the database: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(err: rusqlite::Error) -> Self {
+        ImportError::Database(err)
+    }
+}
+
+/// Parses the contexts and cards of an org file.
+pub fn parse(contents: &str) -> Document {
+    let content_data = Org::parse(contents);
+    let arena = content_data.arena();
+
+    let mut org = Document::new();
+    for headline in content_data.headlines() {
+        org.handle_context(&headline, arena)
+    }
+    org
+}
+
+/// Parses an org or markdown file and syncs its cards with the database.
+/// With `write_ids` the card headlines of an org file without an `:ID:` get
+/// one written into the file first.
+///
+/// Files that didn't change since the last import are skipped, and `None` is
+/// returned for them. The modification time is checked first, and the content
+/// hash only when the file was touched.
+pub fn sync_file(
+    db: &Database,
+    path: &Path,
+    write_ids: bool,
+) -> Result<Option<SyncSummary>, ImportError> {
+    let is_markdown = markdown::is_markdown(path);
+    // Property drawers don't belong into markdown.
+    let write_ids = write_ids && !is_markdown;
+    let file_id = db.insert_file(path)?;
+    let (hash, modified) = db.load_file_state(file_id)?;
+    let mut modified_at = files::modified_time(path)?;
+    // The IDs might still have to be written, so the file must be read.
+    if !write_ids && modified == Some(modified_at) {
+        return Ok(None);
+    }
+
+    let mut contents = fs::read_to_string(path)?;
+    if write_ids {
+        // The IDs are written back so that later edits keep the history.
+        if let Some(updated) = properties::add_missing_ids(&contents) {
+            fs::write(path, &updated)?;
+            contents = updated;
+            modified_at = files::modified_time(path)?;
+        }
+    }
+    let content_hash = files::content_hash(&contents);
+    if hash.as_deref() == Some(content_hash.as_str()) {
+        db.update_file_state(file_id, &content_hash, modified_at)?;
+        return Ok(None);
+    }
+
+    let mut document = if is_markdown {
+        parse(&markdown::to_org(&contents))
+    } else {
+        parse(&contents)
+    };
+    if document.get_title().is_empty() {
+        // The cards of org-drill files can be at the top level.
+        if let Some(name) = path.file_stem() {
+            document.set_title(&name.to_string_lossy());
+        }
+    }
+    let summary = db.sync_document(file_id, &mut document)?;
+    db.update_file_state(file_id, &content_hash, modified_at)?;
+    Ok(Some(summary))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Whether the file is synced again when it is saved. Tables are imported
+/// with a mapping of their columns, which is only known at the import, and
+/// packages are downloads that are imported once.
+pub fn is_watched(path: &Path) -> bool {
+    !matches!(extension(path).as_str(), "csv" | "tsv" | "apkg" | "colpkg")
+}
+
+/// Imports a file of any kind that can be imported: Anki packages, CSV or
+/// TSV tables, and org or markdown files. Tables are imported with the
+/// columns guessed from their first row. Returns the summary of the import,
+/// or `None` for a file that didn't change.
+pub fn import_file(db: &Database, path: &Path, write_ids: bool) -> Result<Option<String>, String> {
+    match extension(path).as_str() {
+        "apkg" | "colpkg" => anki::import_package(db, path)
+            .map(|summary| Some(summary.to_string()))
+            .map_err(|err| err.to_string()),
+        "csv" | "tsv" => {
+            let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+            let records = csv::parse(&text, csv::delimiter(path));
+            let first = records.first().map(|record| record.as_slice());
+            let mapping = csv::Mapping::guess(first.unwrap_or(&[]));
+            let title = path
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let file_id = db.insert_file(path).map_err(|err| err.to_string())?;
+            csv::import(db, &records, &mapping, &title, file_id, false)
+                .map(|summary| Some(summary.to_string()))
+                .map_err(|err| err.to_string())
+        }
+        _ => sync_file(db, path, write_ids)
+            .map(|summary| summary.map(|summary| summary.to_string()))
+            .map_err(|err| err.to_string()),
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| s.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// Finds the files below `root` that match one of the `include` patterns and
+/// none of the `exclude` patterns. Both are comma separated globs. Hidden
+/// files and directories are always skipped, and an excluded directory is
+/// not entered at all.
+pub fn find_files(root: &Path, include: &str, exclude: &str, follow_links: bool) -> Vec<PathBuf> {
+    list_folder(root, root, include, exclude, follow_links, true).1
+}
+
+/// Lists the directories the import enters and the files it reads in `dir`,
+/// a directory of the imported folder `root`, like `find_files`. The entries
+/// below the directories are only listed when `recursive`.
+pub fn list_folder(
+    root: &Path,
+    dir: &Path,
+    include: &str,
+    exclude: &str,
+    follow_links: bool,
+    recursive: bool,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let include = glob::split(include);
+    let exclude = glob::split(exclude);
+    let mut dirs = vec![];
+    let mut files = vec![];
+    let entries = WalkDir::new(dir)
+        .follow_links(follow_links)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            !is_hidden(entry) && !glob::matches_any(&exclude, relative)
+        })
+        // Unreadable entries and symlink loops are skipped.
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.depth() > 0);
+    for entry in entries {
+        if entry.file_type().is_dir() {
+            dirs.push(entry.into_path());
+        } else if entry.file_type().is_file() {
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            if glob::matches_any(&include, relative) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    (dirs, files)
+}
@@ -1,13 +1,46 @@
+mod anki;
+mod cli;
+mod cloze;
+mod csv;
 mod database;
+mod drill;
 mod org;
 mod ui;
 mod files;
+mod glob;
+mod import;
+mod markdown;
+mod optimizer;
+mod profile;
+mod properties;
+mod server;
+mod session;
 mod sr;
+mod tui;
+mod watcher;
 
 use ui::App;
 
 fn main() {
-    let mut app = App::default();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let location = match cli::take_location(&mut args) {
+        Ok(location) => location,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args, &location));
+    }
+
+    let mut app = match App::new(location) {
+        Ok(app) => app,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    };
     app.init();
     let native_options = eframe::NativeOptions {
         ..Default::default()
@@ -0,0 +1,319 @@
+//! Markdown notes, as written in Obsidian or Logseq. They follow the rules of
+//! the org files: a `# Title #context` heading is a context, a
+//! `## Question #card` heading is a card and the headings or the text below
+//! it are the answers.
+//!
+//! The notes are converted to org text and parsed like an org file, so they
+//! end up as the same documents and cards.
+
+use std::path::Path;
+
+/// Checks if the file is a markdown note.
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| matches!(extension.to_ascii_lowercase().as_str(), "md" | "markdown"))
+        .unwrap_or(false)
+}
+
+/// Converts a markdown note into org text.
+pub fn to_org(text: &str) -> String {
+    let mut org = String::new();
+    let mut lines = text.lines().peekable();
+
+    // The front matter holds metadata of the note only.
+    if lines.peek().map(|line| line.trim_end()) == Some("---") {
+        lines.next();
+        for line in lines.by_ref() {
+            if line.trim_end() == "---" {
+                break;
+            }
+        }
+    }
+
+    let mut fence: Option<&str> = None;
+    let mut quote = false;
+    let mut after_heading = false;
+    for line in lines {
+        if let Some(marker) = fence {
+            if line.trim_start().starts_with(marker) {
+                org += "#+end_src\n";
+                fence = None;
+            } else {
+                // Lines that look like org syntax are escaped inside blocks.
+                if line.starts_with('*') || line.starts_with("#+") {
+                    org.push(',');
+                }
+                org += line;
+                org.push('\n');
+            }
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        let is_quote = trimmed.starts_with('>');
+        if quote && !is_quote {
+            org += "#+end_quote\n";
+            quote = false;
+        }
+
+        if let Some(marker) = ["```", "~~~"].iter().find(|m| trimmed.starts_with(**m)) {
+            let language = trimmed[3..].trim_matches(|c| c == '`' || c == '~').trim();
+            org += "#+begin_src";
+            if !language.is_empty() {
+                org.push(' ');
+                org += language;
+            }
+            org.push('\n');
+            fence = Some(marker);
+            after_heading = false;
+            continue;
+        }
+
+        if is_quote {
+            if !quote {
+                org += "#+begin_quote\n";
+                quote = true;
+            }
+            org += &inline(trimmed.trim_start_matches('>').trim_start());
+            org.push('\n');
+            continue;
+        }
+
+        // Logseq writes every block as a list item, headings included.
+        let block = trimmed.strip_prefix("- ").unwrap_or(trimmed);
+        if let Some(heading) = heading(block) {
+            org += &heading;
+            org.push('\n');
+            after_heading = true;
+            continue;
+        }
+
+        if let Some((key, value)) = property(block) {
+            // The block ID of Logseq identifies the card, like an org `:ID:`.
+            if after_heading && key.eq_ignore_ascii_case("id") {
+                org += ":PROPERTIES:\n:ID: ";
+                org += value;
+                org += "\n:END:\n";
+            }
+            continue;
+        }
+        after_heading = false;
+
+        let indent = &line[..line.len() - trimmed.len()];
+        // A `*` bullet would be a headline in org.
+        let item = trimmed
+            .strip_prefix("* ")
+            .or_else(|| trimmed.strip_prefix("+ "));
+        match item {
+            Some(item) => {
+                org += indent;
+                org += "- ";
+                org += &inline(item);
+            }
+            None => {
+                org += indent;
+                org += &inline(trimmed);
+            }
+        }
+        org.push('\n');
+    }
+    if fence.is_some() {
+        org += "#+end_src\n";
+    }
+    if quote {
+        org += "#+end_quote\n";
+    }
+    org
+}
+
+/// Converts an ATX heading into a headline. The `#tags` at the end of the
+/// heading become its tags.
+fn heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let title = line[level..].strip_prefix(' ')?;
+    // Closing hashes are optional in markdown.
+    let mut words: Vec<&str> = title.split_whitespace().collect();
+    while words.last().map(|w| w.chars().all(|c| c == '#')) == Some(true) {
+        words.pop();
+    }
+    let mut tags = vec![];
+    while let Some(tag) = words.last().and_then(|word| word.strip_prefix('#')) {
+        if tag.is_empty() || tag.starts_with('#') {
+            break;
+        }
+        tags.insert(0, tag);
+        words.pop();
+    }
+
+    let mut headline = "*".repeat(level);
+    headline.push(' ');
+    headline += &inline(&words.join(" "));
+    if !tags.is_empty() {
+        headline += " :";
+        headline += &tags.join(":");
+        headline.push(':');
+    }
+    Some(headline)
+}
+
+/// A `key:: value` property line of Logseq.
+fn property(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(":: ")?;
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Some((key, value.trim()))
+    } else {
+        None
+    }
+}
+
+/// Converts the inline markup: emphasis, code, links and images.
+fn inline(text: &str) -> String {
+    let mut org = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' {
+            if let Some(escaped) = rest[1..]
+                .chars()
+                .next()
+                .filter(|c| c.is_ascii_punctuation())
+            {
+                org.push(escaped);
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+        }
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                org.push('~');
+                org += &rest[1..end + 1];
+                org.push('~');
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+        if let Some((marker, org_marker)) = [("**", '*'), ("__", '*'), ("~~", '+')]
+            .iter()
+            .find(|(marker, _)| rest.starts_with(*marker))
+        {
+            if let Some(end) = rest[2..].find(marker).filter(|end| *end > 0) {
+                org.push(*org_marker);
+                org += &inline(&rest[2..end + 2]);
+                org.push(*org_marker);
+                rest = &rest[end + 4..];
+                continue;
+            }
+        }
+        // Underscores inside words, like in snake_case, are no emphasis.
+        let in_word = org.chars().last().map(|c| c.is_alphanumeric()) == Some(true);
+        if c == '*' || (c == '_' && !in_word) {
+            let inner = &rest[1..];
+            if let Some(end) = inner.find(c).filter(|end| *end > 0) {
+                if !inner.starts_with(' ') && !inner[..end].ends_with(' ') {
+                    org.push('/');
+                    org += &inline(&inner[..end]);
+                    org.push('/');
+                    rest = &inner[end + 1..];
+                    continue;
+                }
+            }
+        }
+        if rest.starts_with("[[") {
+            // Wiki links are org links already, only the alias differs.
+            if let Some(end) = rest.find("]]") {
+                match rest[2..end].split_once('|') {
+                    Some((target, alias)) => org += &format!("[[{}][{}]]", target, alias),
+                    None => org += &rest[..end + 2],
+                }
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+        if c == '[' || rest.starts_with("![") {
+            let image = c == '!';
+            let start = if image { 1 } else { 0 };
+            if let Some((label, target, len)) = link(&rest[start..]) {
+                if image {
+                    if target.contains("://") {
+                        org += &format!("[[{}]]", target);
+                    } else {
+                        org += &format!("[[file:{}]]", target);
+                    }
+                } else {
+                    org += &format!("[[{}][{}]]", target, inline(label));
+                }
+                rest = &rest[start + len..];
+                continue;
+            }
+        }
+        org.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    org
+}
+
+/// Parses a `[label](target)` link. Returns the label, the target and the
+/// length of the link.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let label_end = text.find("](")?;
+    let label = text.get(1..label_end)?;
+    if label.contains(['[', ']']) {
+        return None;
+    }
+    let target_end = label_end + 2 + text[label_end + 2..].find(')')?;
+    let target = text[label_end + 2..target_end].trim();
+    // A title after the target is dropped.
+    let target = target.split_whitespace().next().unwrap_or("");
+    Some((label, target, target_end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heading_tags() {
+        assert_eq!(
+            heading("## What is Rust? #card").as_deref(),
+            Some("** What is Rust? :card:")
+        );
+        assert_eq!(
+            heading("# Languages #context #lang ##").as_deref(),
+            Some("* Languages :context:lang:")
+        );
+        assert_eq!(
+            heading("## C# in #1 place").as_deref(),
+            Some("** C# in #1 place")
+        );
+        assert_eq!(heading("#card"), None);
+        assert_eq!(heading("####### too deep"), None);
+    }
+
+    #[test]
+    fn note_to_org() {
+        let note = "---\ntags: notes\n---\n# Rust #context\n## Who made **Rust**? #card\n\
+                    - id:: 6f1c\n### Graydon *Hoare*\n* a `bullet`\n```rust\n* not a headline\n```\n";
+        assert_eq!(
+            to_org(note),
+            "* Rust :context:\n** Who made *Rust*? :card:\n:PROPERTIES:\n:ID: 6f1c\n:END:\n\
+             *** Graydon /Hoare/\n- a ~bullet~\n#+begin_src rust\n,* not a headline\n#+end_src\n"
+        );
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(
+            inline("see [the book](https://doc.rust-lang.org \"title\") and [[Ownership|owning]]"),
+            "see [[https://doc.rust-lang.org][the book]] and [[Ownership][owning]]"
+        );
+        assert_eq!(inline("![logo](img/rust.png)"), "[[file:img/rust.png]]");
+        assert_eq!(inline("snake_case_name"), "snake_case_name");
+    }
+}
@@ -0,0 +1,184 @@
+//! Fits the FSRS weights to the local review history.
+//!
+//! Every review after the first one of a card is a prediction of recall. The
+//! weights are tuned with gradient descent on the log-loss of these
+//! predictions. Everything runs offline on the reviews in the database.
+
+use chrono::NaiveDate;
+
+use crate::sr::{Fsrs, Grade, Review, Stats};
+
+/// Lower and upper bounds of every weight. They keep the memory model sane
+/// when there are only a few reviews to learn from.
+const BOUNDS: [(f64, f64); 17] = [
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (1.0, 10.0),
+    (0.1, 5.0),
+    (0.1, 5.0),
+    (0.0, 0.75),
+    (0.0, 4.0),
+    (0.0, 0.8),
+    (0.01, 3.0),
+    (0.1, 5.0),
+    (0.01, 0.2),
+    (0.01, 0.9),
+    (0.01, 3.0),
+    (0.0, 1.0),
+    (1.0, 6.0),
+];
+
+const ITERATIONS: usize = 200;
+const STEP: f64 = 1e-4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    pub log_loss: f64,
+    pub rmse: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub before: Metrics,
+    pub after: Metrics,
+    pub weights: [f64; 17],
+    /// Number of predictions the weights were fitted on.
+    pub predictions: usize,
+}
+
+/// The answers of one card, one per day, with the days elapsed since the
+/// previous answer.
+type History = Vec<(Grade, f64)>;
+
+/// Groups the reviews by card. Only cards whose history starts as a new card
+/// are used, since the memory state before the log began is unknown.
+fn histories(reviews: &[Review]) -> Vec<History> {
+    let mut histories = vec![];
+    let mut card = None;
+    let mut history: History = vec![];
+    let mut last_day: Option<NaiveDate> = None;
+    let mut usable = false;
+    for review in reviews {
+        if card != Some(review.card_id) {
+            if usable && history.len() > 1 {
+                histories.push(history);
+            }
+            history = vec![];
+            card = Some(review.card_id);
+            last_day = None;
+            usable = review.previous_interval == 0;
+        }
+        let day = review.reviewed_at.date().naive_utc();
+        match last_day {
+            // Only the first answer of a day is counted.
+            Some(last) if last == day => continue,
+            Some(last) => history.push((review.grade, (day - last).num_days() as f64)),
+            None => history.push((review.grade, 0.0)),
+        }
+        last_day = Some(day);
+    }
+    if usable && history.len() > 1 {
+        histories.push(history);
+    }
+    histories
+}
+
+/// Replays every history with the given weights and measures how well the
+/// predicted recall matches the answers.
+fn evaluate(weights: &[f64; 17], histories: &[History]) -> Metrics {
+    let fsrs = Fsrs::new(*weights);
+    let mut loss = 0.0;
+    let mut squared = 0.0;
+    let mut count = 0.0;
+    for history in histories {
+        let mut stats = Stats::new();
+        for (idx, (grade, elapsed)) in history.iter().enumerate() {
+            if idx > 0 {
+                let r = Fsrs::retrievability(*elapsed, stats.stability).clamp(1e-6, 1.0 - 1e-6);
+                let y = if grade.is_correct() { 1.0 } else { 0.0 };
+                loss -= y * r.ln() + (1.0 - y) * (1.0 - r).ln();
+                squared += (r - y) * (r - y);
+                count += 1.0;
+            }
+            fsrs.step(&mut stats, *grade, *elapsed);
+        }
+    }
+    Metrics {
+        log_loss: loss / count,
+        rmse: (squared / count).sqrt(),
+    }
+}
+
+/// The weights are optimized in a space where every bound maps to 0..1, so a
+/// single learning rate fits all of them.
+fn normalize(weights: &[f64; 17]) -> [f64; 17] {
+    let mut x = [0.0; 17];
+    for (i, (low, high)) in BOUNDS.iter().enumerate() {
+        x[i] = ((weights[i] - low) / (high - low)).clamp(0.0, 1.0);
+    }
+    x
+}
+
+fn denormalize(x: &[f64; 17]) -> [f64; 17] {
+    let mut weights = [0.0; 17];
+    for (i, (low, high)) in BOUNDS.iter().enumerate() {
+        weights[i] = low + x[i].clamp(0.0, 1.0) * (high - low);
+    }
+    weights
+}
+
+/// Fits the weights starting from `initial`. Returns `None` when there is no
+/// card with at least two days of answers in the history.
+pub fn optimize(reviews: &[Review], initial: &[f64; 17]) -> Option<Report> {
+    let histories = histories(reviews);
+    let predictions: usize = histories.iter().map(|history| history.len() - 1).sum();
+    if predictions == 0 {
+        return None;
+    }
+
+    let loss = |x: &[f64; 17]| evaluate(&denormalize(x), &histories).log_loss;
+    let mut x = normalize(initial);
+    let mut current = loss(&x);
+    let mut rate = 0.05;
+    for _ in 0..ITERATIONS {
+        // Central differences are cheap enough for 17 weights.
+        let mut gradient = [0.0; 17];
+        for i in 0..17 {
+            let mut up = x;
+            let mut down = x;
+            up[i] += STEP;
+            down[i] -= STEP;
+            gradient[i] = (loss(&up) - loss(&down)) / (2.0 * STEP);
+        }
+
+        let mut next = x;
+        for i in 0..17 {
+            next[i] = (x[i] - rate * gradient[i]).clamp(0.0, 1.0);
+        }
+        let next_loss = loss(&next);
+        if next_loss < current {
+            if current - next_loss < 1e-7 {
+                x = next;
+                break;
+            }
+            x = next;
+            current = next_loss;
+        } else {
+            // Overshot the minimum, take smaller steps.
+            rate *= 0.5;
+            if rate < 1e-6 {
+                break;
+            }
+        }
+    }
+
+    let weights = denormalize(&x);
+    Some(Report {
+        before: evaluate(initial, &histories),
+        after: evaluate(&weights, &histories),
+        weights,
+        predictions,
+    })
+}
@@ -1,25 +1,80 @@
 //! It holds the necessary org structure like Content, Questions and answers
 //! In unformatted structure.
 
+use chrono::{Duration, NaiveDate};
 use orgize::{
     elements::List,
-    indextree::{Arena, NodeId},
+    export::{DefaultOrgHandler, OrgHandler},
+    indextree::{Arena, NodeEdge, NodeId},
 };
-use orgize::{Element, Headline};
+use orgize::{elements::Title, Element, Headline};
 use rusqlite::Row;
 
 use std::borrow::Cow;
 
-use crate::sr::Stats;
+use crate::{
+    cloze,
+    drill::{self, CardKind},
+    sr::Stats,
+};
 
 // Use it to get the stats.
 #[derive(Debug, Clone)]
 pub struct FlashCard {
     id: i64,
+    /// The `:ID:` property of the card headline. It identifies the card even
+    /// when the question is edited.
+    uid: Option<String>,
     doc_id: i64,
     questions: String,
     answers: String,
+    /// Every answer headline on its own. Used to fill cloze gaps.
+    parts: Vec<String>,
+    /// Org source of the text written below the card and answer headlines.
+    body: String,
     stats: Stats,
+    /// The date the card was due in org-drill, for a card that is imported
+    /// with its schedule.
+    scheduled: Option<NaiveDate>,
+    /// The answer headlines of org-drill cards are labels, like `Answer`, so
+    /// they are kept as headings in the body.
+    drill: bool,
+    /// The number of times the card was drilled in org-drill, before the
+    /// answers in the review log.
+    drill_repeats: i64,
+    tags: Vec<String>,
+}
+
+/// Checks if the headline is a card, either native or from org-drill.
+/// The tags that make a headline a card, the own one and the one of
+/// org-drill.
+pub const CARD_TAGS: [&str; 2] = ["card", "drill"];
+
+fn is_card(title: &Title) -> bool {
+    title.tags.iter().any(|tag| CARD_TAGS.contains(&tag.as_ref()))
+}
+
+/// The tags of the headline and the ones it inherits from the headlines above
+/// it, like in org, the outermost first. The tags that mark cards and
+/// contexts are left out.
+fn inherited_tags<'a>(id: NodeId, arena: &Arena<Element<'a>>) -> Vec<String> {
+    let titles: Vec<&Title> = id
+        .ancestors(arena)
+        .filter_map(|node| {
+            node.children(arena).find_map(|child| match arena[child].get() {
+                Element::Title(title) => Some(title),
+                _ => None,
+            })
+        })
+        .collect();
+    let mut tags: Vec<String> = vec![];
+    for tag in titles.iter().rev().flat_map(|title| title.tags.iter()) {
+        if !matches!(tag.as_ref(), "card" | "drill" | "context") && !tags.iter().any(|t| t == tag)
+        {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
 }
 
 #[derive(Debug, Clone)]
@@ -45,10 +100,17 @@ impl FlashCard {
     pub fn new() -> Self {
         FlashCard {
             id: 0,
+            uid: None,
             doc_id: 0,
             questions: String::new(),
             answers: String::new(),
+            parts: vec![],
+            body: String::new(),
             stats: Stats::new(),
+            scheduled: None,
+            drill: false,
+            drill_repeats: 0,
+            tags: vec![],
         }
     }
 
@@ -56,21 +118,23 @@ impl FlashCard {
         questions: &str,
         answers: &str,
         id: i64,
+        uid: Option<String>,
         doc_id: i64,
-        intervals: i64,
-        reps: i16,
-        difficulty: f64,
+        stats: Stats,
     ) -> Self {
         FlashCard {
             id,
+            uid,
             doc_id,
             questions: questions.to_string(),
             answers: answers.to_string(),
-            stats: Stats {
-                interval: intervals,
-                num_reps: reps,
-                difficultly: difficulty,
-            },
+            parts: vec![],
+            body: String::new(),
+            stats,
+            scheduled: None,
+            drill: false,
+            drill_repeats: 0,
+            tags: vec![],
         }
     }
 
@@ -79,8 +143,24 @@ impl FlashCard {
     }
 
     pub fn add_answer(&mut self, answer: &str) {
-        self.answers += answer;
-        self.answers += ", ";
+        self.parts.push(answer.trim().to_owned());
+    }
+
+    pub fn add_body(&mut self, body: &str) {
+        self.body += body;
+    }
+
+    /// Joins the answer headlines and the body into the answer. The answer is
+    /// kept as org source so that it can be rendered with formatting.
+    fn finish_answers(&mut self) {
+        self.answers = self.parts.join(", ");
+        let body = self.body.trim_end();
+        if !body.is_empty() {
+            if !self.answers.is_empty() {
+                self.answers += "\n\n";
+            }
+            self.answers += body;
+        }
     }
 
     pub fn get_doc_id(&self) -> i64 {
@@ -91,6 +171,10 @@ impl FlashCard {
         self.id
     }
 
+    pub fn get_uid(&self) -> Option<&str> {
+        self.uid.as_deref()
+    }
+
     pub fn get_questions(&self) -> &str {
         &self.questions
     }
@@ -106,6 +190,30 @@ impl FlashCard {
     pub fn get_stats_mut(&mut self) -> &mut Stats {
         &mut self.stats
     }
+
+    pub fn get_scheduled(&self) -> Option<NaiveDate> {
+        self.scheduled
+    }
+
+    pub fn set_scheduled(&mut self, scheduled: Option<NaiveDate>) {
+        self.scheduled = scheduled;
+    }
+
+    pub fn get_drill_repeats(&self) -> i64 {
+        self.drill_repeats
+    }
+
+    pub fn set_drill_repeats(&mut self, repeats: i64) {
+        self.drill_repeats = repeats;
+    }
+
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn set_tags<'a>(&mut self, tags: impl IntoIterator<Item = &'a str>) {
+        self.tags = tags.into_iter().map(|tag| tag.to_owned()).collect();
+    }
 }
 
 impl Document {
@@ -123,8 +231,8 @@ impl Document {
             let data = arena.get(child).unwrap().get();
             // Check if this is a some other element like code block.
             if let Element::Title(title) = data {
-                if !(title.tags.contains(&Cow::Borrowed("card"))) {
-                    self.handle_normal_headline(id, arena, &2, usize::MAX);
+                if !is_card(title) {
+                    self.handle_normal_headline(id, arena, &2);
                 } else {
                     self.handle_flashcards(id, arena)
                 }
@@ -137,33 +245,25 @@ impl Document {
         id: NodeId,
         arena: &Arena<Element<'a>>,
         level: &usize,
-        idx: usize,
     ) {
         for child in id.children(arena) {
             let data = arena.get(child).unwrap().get();
             match data {
                 Element::Section => self.handle_section(child, arena),
                 Element::Headline { level: size } => {
-                    self.handle_normal_headline(child, arena, size, idx)
+                    self.handle_normal_headline(child, arena, size)
                 }
                 Element::Title(title) => {
-                    if title.tags.contains(&Cow::Borrowed("card")) {
+                    if is_card(title) {
                         self.handle_flashcards(id, arena);
                         return;
                     }
-                    if usize::MAX > idx {
-                        // Do not touch this. It is deep in recursion.
-                        if let Some(flashcard) = self.cards.get_mut(idx as usize) {
-                            flashcard.add_answer(&title.raw);
-                        }
-                    } else {
-                        for _ in 0..*level {
-                            self.content += "*";
-                        }
-                        self.content += " ";
-                        self.content += &title.raw;
-                        self.content += "\n";
+                    for _ in 0..*level {
+                        self.content += "*";
                     }
+                    self.content += " ";
+                    self.content += &title.raw;
+                    self.content += "\n";
                 }
                 _ => {}
             }
@@ -174,7 +274,7 @@ impl Document {
             let data = arena.get(child).unwrap().get();
             match data {
                 Element::Text { value: text } => {
-                    self.content += &text;
+                    self.content += text;
                 }
                 Element::Bold => {
                     self.content += "*";
@@ -233,22 +333,158 @@ impl Document {
     }
 
     pub fn handle_flashcards<'a>(&mut self, id: NodeId, arena: &Arena<Element<'a>>) {
+        let idx = self.cards.len();
+        let mut body = String::new();
+        let mut kind = CardKind::Basic;
         for child_id in id.children(arena) {
             let data = arena.get(child_id).unwrap().get();
             match data {
                 Element::Title(title) => {
+                    let property = |key: &str| {
+                        title
+                            .properties
+                            .iter()
+                            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                            .map(|(_, value)| value.trim().to_string())
+                    };
                     let mut flash_card = FlashCard::new();
                     flash_card.add_question(&title.raw);
+                    flash_card.uid = property("ID");
+                    flash_card.drill = title.tags.iter().any(|tag| tag == "drill");
+                    flash_card.tags = inherited_tags(id, arena);
+                    // Cards drilled before start from their org-drill schedule.
+                    if let Some((stats, repeats)) = drill::read_stats(property) {
+                        flash_card.scheduled = title
+                            .planning
+                            .as_ref()
+                            .and_then(|planning| planning.scheduled.as_ref())
+                            .and_then(drill::timestamp_date)
+                            .or_else(|| {
+                                stats
+                                    .last_review
+                                    .map(|date| date + Duration::days(stats.interval))
+                            });
+                        flash_card.stats = stats;
+                        flash_card.drill_repeats = repeats;
+                    }
+                    kind = CardKind::from_card_type(property(drill::CARD_TYPE).as_deref());
                     self.cards.push(flash_card);
                 }
-                Element::Headline { level } => {
-                    self.handle_normal_headline(child_id, arena, level, self.cards.len() - 1);
+                Element::Section => body = Document::org_source(child_id, arena),
+                Element::Headline { .. } => self.handle_answer(child_id, arena, idx),
+                _ => {}
+            }
+        }
+        if kind == CardKind::Cloze {
+            body = drill::convert_clozes(&body);
+        }
+        if self.expand_cloze(idx, &body) {
+            return;
+        }
+        if let Some(card) = self.cards.get_mut(idx) {
+            if card.drill && !body.trim().is_empty() {
+                // The headline of an org-drill card is only a label, the text
+                // below it is the question and the headlines are the answer.
+                card.questions = body.trim().to_owned();
+            } else {
+                card.body = body + &card.body;
+            }
+            card.finish_answers();
+        }
+    }
+
+    /// Every headline below a card is an answer. The title and the text below
+    /// it are added to the card at `idx`.
+    fn handle_answer<'a>(&mut self, id: NodeId, arena: &Arena<Element<'a>>, idx: usize) {
+        for child in id.children(arena) {
+            let data = arena.get(child).unwrap().get();
+            match data {
+                Element::Title(title) => {
+                    if is_card(title) {
+                        self.handle_flashcards(id, arena);
+                        return;
+                    }
+                    if let Some(flashcard) = self.cards.get_mut(idx) {
+                        if flashcard.drill {
+                            flashcard.add_body(&format!(
+                                "{} {}\n",
+                                "*".repeat(title.level),
+                                title.raw
+                            ));
+                        } else {
+                            flashcard.add_answer(&title.raw);
+                        }
+                    }
+                }
+                Element::Section => {
+                    let body = Document::org_source(child, arena);
+                    if let Some(flashcard) = self.cards.get_mut(idx) {
+                        flashcard.add_body(&body);
+                    }
                 }
+                Element::Headline { .. } => self.handle_answer(child, arena, idx),
                 _ => {}
             }
         }
     }
 
+    /// Replaces the card at `idx` with a card for every cloze deletion. The
+    /// deletions are looked up in the question first, then in the body.
+    fn expand_cloze(&mut self, idx: usize, body: &str) -> bool {
+        let card = match self.cards.get(idx) {
+            Some(card) => card,
+            None => return false,
+        };
+        let clozes = if cloze::has_cloze(&card.questions) {
+            cloze::expand(&card.questions)
+        } else if cloze::has_cloze(body) {
+            cloze::expand(body.trim())
+        } else {
+            cloze::expand_gaps(&card.questions, &card.parts)
+        };
+        if clozes.is_empty() {
+            return false;
+        }
+        // Every deletion gets its own identity derived from the headline. An
+        // imported schedule belongs to the headline, so they all start from it.
+        let uid = card.uid.clone();
+        let stats = card.stats.clone();
+        let scheduled = card.scheduled;
+        let drill_repeats = card.drill_repeats;
+        let tags = card.tags.clone();
+        let cards: Vec<FlashCard> = clozes
+            .into_iter()
+            .map(|(number, question, answer)| {
+                let mut flash_card = FlashCard::new();
+                flash_card.add_question(&question);
+                flash_card.answers = answer;
+                // The number of the deletion, so removing `c1` keeps the
+                // identity of `c2`.
+                flash_card.uid = uid.as_ref().map(|uid| format!("{}-c{}", uid, number));
+                flash_card.stats = stats.clone();
+                flash_card.scheduled = scheduled;
+                flash_card.drill_repeats = drill_repeats;
+                flash_card.tags = tags.clone();
+                flash_card
+            })
+            .collect();
+        self.cards.splice(idx..idx + 1, cards);
+        true
+    }
+
+    /// Writes the element and everything within it back as org text.
+    pub fn org_source<'a>(id: NodeId, arena: &Arena<Element<'a>>) -> String {
+        let mut handler = DefaultOrgHandler;
+        let mut source: Vec<u8> = vec![];
+        for edge in id.traverse(arena) {
+            match edge {
+                NodeEdge::Start(node) => handler.start(&mut source, arena[node].get()).ok(),
+                NodeEdge::End(node) => handler.end(&mut source, arena[node].get()).ok(),
+            };
+        }
+        String::from_utf8_lossy(&source).into_owned()
+    }
+
     /// This function is responsible for fetching the context. It is the source of
     /// truth.
     pub fn handle_context<'a>(&mut self, headline: &'a Headline, arena: &Arena<Element<'a>>) {
@@ -256,14 +492,25 @@ impl Document {
         let node = arena.get(node_id).unwrap().get();
         // We make sure that we are only accessing the context title.
         if let Element::Title(title) = node {
+            let is_context = title.tags.contains(&Cow::Borrowed("context"));
+            // org-drill files have no contexts, so their top level headlines
+            // with cards are used instead.
+            let has_drill =
+                headline.level() == 1 && Document::has_drill_cards(headline.headline_node(), arena);
             // We yeet out if this is not a context.
-            if !(title.tags.contains(&Cow::Borrowed("context"))) {
+            if !is_context && !has_drill {
+                return;
+            }
+            if !is_context && is_card(title) {
+                self.handle_flashcards(headline.headline_node(), arena);
                 return;
             }
             self.content += "* ";
             self.content += &title.raw;
             self.title += &title.raw;
-            self.content += " :context:";
+            if is_context {
+                self.content += " :context:";
+            }
             self.content += "\n";
             // We will access the section data for this context. The actual data lies
             // Inside the root node. Therefore we will call the handle section with
@@ -277,6 +524,17 @@ impl Document {
         }
     }
 
+    fn has_drill_cards<'a>(id: NodeId, arena: &Arena<Element<'a>>) -> bool {
+        id.descendants(arena).any(|node| match arena[node].get() {
+            Element::Title(title) => title.tags.iter().any(|tag| tag == "drill"),
+            _ => false,
+        })
+    }
+
+    pub fn get_id(&self) -> i64 {
+        self.id
+    }
+
     pub fn get_contents(&self) -> &str {
         &self.content
     }
@@ -285,6 +543,10 @@ impl Document {
         &self.title
     }
 
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_owned();
+    }
+
     pub fn get_cards(&self) -> &Vec<FlashCard> {
         &self.cards
     }
@@ -0,0 +1,151 @@
+//! Where the collection is stored. Every profile has its own database in the
+//! data directory of the platform, so the cards of work, home or a language
+//! don't mix, and the collection doesn't depend on the directory the app was
+//! started in.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use directories_next::ProjectDirs;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The database of an older version, which was kept in the directory the
+/// app was started in.
+const LEGACY_DATABASE: &str = "./database.db3";
+
+/// A profile and the path of its database.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub profile: String,
+    pub database: PathBuf,
+    /// The database was given with `--database` or `FLASHCARDS_DB`, so the
+    /// profiles of the data directory are not switched to.
+    pub explicit: bool,
+}
+
+impl Location {
+    /// The database path is taken from `database`, then from the
+    /// `FLASHCARDS_DB` variable. Without either it is the file of the profile
+    /// in the data directory. The profile is taken from `profile`, then from
+    /// the `FLASHCARDS_PROFILE` variable.
+    pub fn resolve(database: Option<&str>, profile: Option<&str>) -> Result<Self, String> {
+        let profile = match profile {
+            Some(profile) => profile.to_owned(),
+            None => env::var("FLASHCARDS_PROFILE").unwrap_or_else(|_| DEFAULT_PROFILE.to_owned()),
+        };
+        if !is_valid_name(&profile) {
+            return Err(format!(
+                "{} is not a profile name, use letters, digits, - and _",
+                profile
+            ));
+        }
+        let database = database.map(|path| path.to_owned()).or_else(|| {
+            env::var("FLASHCARDS_DB")
+                .ok()
+                .filter(|path| !path.is_empty())
+        });
+        match database {
+            Some(database) => Ok(Location {
+                profile,
+                database: PathBuf::from(database),
+                explicit: true,
+            }),
+            None => Ok(Location::of_profile(&profile)),
+        }
+    }
+
+    /// The database of the profile in the data directory.
+    pub fn of_profile(profile: &str) -> Self {
+        let location = Location {
+            profile: profile.to_owned(),
+            database: database_of(profile),
+            explicit: false,
+        };
+        if profile == DEFAULT_PROFILE {
+            location.adopt_legacy_database();
+        }
+        location
+    }
+
+    /// Copies the database of an older version to the data directory, the
+    /// first time the default profile is used.
+    fn adopt_legacy_database(&self) {
+        let legacy = Path::new(LEGACY_DATABASE);
+        if self.database.exists() || !legacy.is_file() {
+            return;
+        }
+        if let Some(parent) = self.database.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                println!("{}: {}", parent.display(), err);
+                return;
+            }
+        }
+        match fs::copy(legacy, &self.database) {
+            Ok(_) => println!("Copied {} to {}", legacy.display(), self.database.display()),
+            Err(err) => println!("{}: {}", self.database.display(), err),
+        }
+    }
+}
+
+/// The path of the database of the profile in the data directory.
+pub fn database_of(profile: &str) -> PathBuf {
+    data_dir().join(format!("{}.db3", profile))
+}
+
+/// Profile names become file names, so they are kept simple.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The data directory of the platform, like `~/.local/share/flashcards`.
+/// Without a home directory it is the current directory.
+pub fn data_dir() -> PathBuf {
+    ProjectDirs::from("", "", "flashcards")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// The profiles that have a database in the data directory, and the default
+/// profile, by name.
+pub fn profiles() -> Vec<String> {
+    let mut profiles = vec![DEFAULT_PROFILE.to_owned()];
+    if let Ok(entries) = fs::read_dir(data_dir()) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("db3") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                if is_valid_name(name) && !profiles.iter().any(|profile| profile == name) {
+                    profiles.push(name.to_owned());
+                }
+            }
+        }
+    }
+    profiles[1..].sort();
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_database() {
+        let location = Location::resolve(Some("/tmp/cards.db3"), Some("work")).unwrap();
+        assert!(location.explicit);
+        assert_eq!(location.database, PathBuf::from("/tmp/cards.db3"));
+        assert_eq!(location.profile, "work");
+
+        let location = Location::of_profile("work");
+        assert!(!location.explicit);
+        assert_eq!(location.database, database_of("work"));
+        assert!(Location::resolve(None, Some("../work")).is_err());
+    }
+}
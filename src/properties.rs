@@ -0,0 +1,295 @@
+//! Edits the `:PROPERTIES:` drawers of headlines directly in the org text, so
+//! that the rest of the file stays exactly as it was written.
+
+use uuid::Uuid;
+
+use crate::org::CARD_TAGS;
+
+/// Checks if the line is a headline with the given tag.
+pub fn is_tagged_headline(line: &str, tag: &str) -> bool {
+    let stars = line.chars().take_while(|c| *c == '*').count();
+    if stars == 0 || !line[stars..].starts_with(' ') {
+        return false;
+    }
+    match line.trim_end().rsplit(char::is_whitespace).next() {
+        Some(tags) if tags.len() > 1 && tags.starts_with(':') && tags.ends_with(':') => {
+            tags.split(':').any(|t| t == tag)
+        }
+        _ => false,
+    }
+}
+
+/// Checks if the line is the headline of a card, like `org::is_card` does
+/// for a parsed headline.
+pub fn is_card_headline(line: &str) -> bool {
+    CARD_TAGS.iter().any(|tag| is_tagged_headline(line, tag))
+}
+
+/// The keywords the parser knows, which are not part of the title.
+const KEYWORDS: [&str; 2] = ["TODO", "DONE"];
+
+fn headline_level(line: &str) -> Option<usize> {
+    let stars = line.chars().take_while(|c| *c == '*').count();
+    if stars > 0 && line[stars..].starts_with(' ') {
+        Some(stars)
+    } else {
+        None
+    }
+}
+
+/// Returns the title of a headline line, without the stars, the keyword, the
+/// priority and the tags, like the parser reads it.
+pub fn headline_title(line: &str) -> Option<&str> {
+    let stars = headline_level(line)?;
+    let rest = line[stars + 1..].trim();
+    let rest = match rest.split_once(' ') {
+        Some((keyword, title)) if KEYWORDS.contains(&keyword) => title.trim_start(),
+        _ if KEYWORDS.contains(&rest) => "",
+        _ => rest,
+    };
+    // A priority is a single letter or digit, like `[#A]`.
+    let rest = rest
+        .strip_prefix("[#")
+        .and_then(|after| {
+            let priority = after.chars().next()?;
+            after[priority.len_utf8()..].strip_prefix(']')
+        })
+        .map_or(rest, str::trim_start);
+    let title = match rest.rsplit_once(char::is_whitespace) {
+        Some((title, tags)) if tags.len() > 1 && tags.starts_with(':') && tags.ends_with(':') => {
+            title
+        }
+        _ => rest,
+    };
+    Some(title.trim())
+}
+
+fn is_planning(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("SCHEDULED:") || line.starts_with("DEADLINE:") || line.starts_with("CLOSED:")
+}
+
+/// Returns the range of lines of the property drawer that belongs to the
+/// headline at `idx`, if there is one. The range includes both delimiters.
+fn drawer_range(lines: &[String], idx: usize) -> Option<(usize, usize)> {
+    let mut start = idx + 1;
+    if lines.get(start).map(|l| is_planning(l)).unwrap_or(false) {
+        start += 1;
+    }
+    if !lines.get(start)?.trim().eq_ignore_ascii_case(":PROPERTIES:") {
+        return None;
+    }
+    let end = lines[start..]
+        .iter()
+        .position(|l| l.trim().eq_ignore_ascii_case(":END:"))?;
+    Some((start, start + end))
+}
+
+/// Returns the text below the headline at `idx` up to the next headline,
+/// without the planning line and the property drawer.
+pub fn section(lines: &[String], idx: usize) -> String {
+    let mut start = idx + 1;
+    if lines.get(start).map(|l| is_planning(l)).unwrap_or(false) {
+        start += 1;
+    }
+    if let Some((_, end)) = drawer_range(lines, idx) {
+        start = end + 1;
+    }
+    let mut text = String::new();
+    for line in lines.iter().skip(start) {
+        if headline_level(line).is_some() {
+            break;
+        }
+        text += line;
+        text += "\n";
+    }
+    text
+}
+
+/// Returns the titles of the headlines below the headline at `idx`, the
+/// answers of a card.
+pub fn subheadline_titles(lines: &[String], idx: usize) -> Vec<&str> {
+    let level = match headline_level(&lines[idx]) {
+        Some(level) => level,
+        None => return vec![],
+    };
+    lines[idx + 1..]
+        .iter()
+        .filter(|line| headline_level(line).is_some())
+        .take_while(|line| headline_level(line) > Some(level))
+        .filter_map(|line| headline_title(line))
+        .collect()
+}
+
+/// Returns the value of a property of the headline at `idx`.
+pub fn get_property(lines: &[String], idx: usize, key: &str) -> Option<String> {
+    let (start, end) = drawer_range(lines, idx)?;
+    lines[start + 1..end].iter().find_map(|line| {
+        let line = line.trim();
+        let rest = line.strip_prefix(':')?;
+        let (name, value) = rest.split_once(':')?;
+        if name.eq_ignore_ascii_case(key) {
+            Some(value.trim().to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+/// Sets a property of the headline at `idx`, creating the drawer if needed.
+pub fn set_property(lines: &mut Vec<String>, idx: usize, key: &str, value: &str) {
+    let entry = format!(":{}: {}", key, value);
+    match drawer_range(lines, idx) {
+        Some((start, end)) => {
+            let existing = (start + 1..end).find(|i| {
+                lines[*i]
+                    .trim()
+                    .strip_prefix(':')
+                    .and_then(|rest| rest.split_once(':'))
+                    .map(|(name, _)| name.eq_ignore_ascii_case(key))
+                    .unwrap_or(false)
+            });
+            match existing {
+                Some(i) => lines[i] = entry,
+                None => lines.insert(end, entry),
+            }
+        }
+        None => {
+            let mut start = idx + 1;
+            if lines.get(start).map(|l| is_planning(l)).unwrap_or(false) {
+                start += 1;
+            }
+            lines.insert(start, String::from(":PROPERTIES:"));
+            lines.insert(start + 1, entry);
+            lines.insert(start + 2, String::from(":END:"));
+        }
+    }
+}
+
+/// Sets the `SCHEDULED:` timestamp of the headline at `idx`. A `DEADLINE:`
+/// on the planning line is kept.
+pub fn set_scheduled(lines: &mut Vec<String>, idx: usize, timestamp: &str) {
+    let entry = format!("SCHEDULED: {}", timestamp);
+    let planning = idx + 1;
+    if !lines.get(planning).map(|l| is_planning(l)).unwrap_or(false) {
+        lines.insert(planning, entry);
+        return;
+    }
+    let line = &lines[planning];
+    lines[planning] = match line.find("SCHEDULED:") {
+        Some(start) => {
+            let rest = &line[start + "SCHEDULED:".len()..];
+            let trimmed = rest.trim_start();
+            // The timestamp ends with the closing bracket.
+            let end = match trimmed.chars().next() {
+                Some('<') => trimmed.find('>').map(|i| i + 1),
+                Some('[') => trimmed.find(']').map(|i| i + 1),
+                _ => None,
+            }
+            .unwrap_or(0);
+            format!("{}{}{}", &line[..start], entry, &trimmed[end..])
+        }
+        None => format!("{} {}", line.trim_end(), entry),
+    };
+}
+
+/// Gives every card headline an `:ID:` property, if it has none. Returns the
+/// new text, or `None` if nothing had to change.
+pub fn add_missing_ids(text: &str) -> Option<String> {
+    let mut lines: Vec<String> = text.lines().map(|l| l.to_owned()).collect();
+    let mut changed = false;
+    let mut idx = 0;
+    while idx < lines.len() {
+        if is_card_headline(&lines[idx]) && get_property(&lines, idx, "ID").is_none() {
+            let id = Uuid::new_v4().to_string();
+            set_property(&mut lines, idx, "ID", &id);
+            changed = true;
+        }
+        idx += 1;
+    }
+    if !changed {
+        return None;
+    }
+    let mut result = lines.join("\n");
+    if text.ends_with('\n') {
+        result += "\n";
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|l| l.to_owned()).collect()
+    }
+
+    #[test]
+    fn read_headline_titles() {
+        assert_eq!(
+            headline_title("** TODO [#A] Capital of France :card:"),
+            Some("Capital of France")
+        );
+        assert_eq!(headline_title("* DONE"), Some(""));
+        assert_eq!(headline_title("*bold* text"), None);
+        assert!(is_card_headline("* Question :geo:card:"));
+        assert!(!is_card_headline("* Question :geo:"));
+    }
+
+    #[test]
+    fn set_property_creates_and_replaces_the_drawer() {
+        let mut text = lines("* Question :card:\nSCHEDULED: <2022-04-01 Fri>\nBody\n");
+        set_property(&mut text, 0, "ID", "abc");
+        assert_eq!(text[2], ":PROPERTIES:");
+        assert_eq!(get_property(&text, 0, "id").as_deref(), Some("abc"));
+        set_property(&mut text, 0, "ID", "def");
+        set_property(&mut text, 0, "DRILL_EASE", "2.5");
+        assert_eq!(
+            text,
+            lines(
+                "* Question :card:\nSCHEDULED: <2022-04-01 Fri>\n:PROPERTIES:\n:ID: def\n\
+                 :DRILL_EASE: 2.5\n:END:\nBody"
+            )
+        );
+        assert_eq!(section(&text, 0), "Body\n");
+    }
+
+    #[test]
+    fn set_scheduled_keeps_the_deadline() {
+        let mut text = lines("* Question :card:\nBody");
+        set_scheduled(&mut text, 0, "<2022-04-01 Fri>");
+        assert_eq!(text[1], "SCHEDULED: <2022-04-01 Fri>");
+
+        let mut text =
+            lines("* Question :card:\nDEADLINE: <2022-05-01 Sun> SCHEDULED: <2022-04-01 Fri>");
+        set_scheduled(&mut text, 0, "<2022-04-09 Sat>");
+        assert_eq!(
+            text[1],
+            "DEADLINE: <2022-05-01 Sun> SCHEDULED: <2022-04-09 Sat>"
+        );
+
+        let mut text = lines("* Question :card:\nDEADLINE: <2022-05-01 Sun>");
+        set_scheduled(&mut text, 0, "<2022-04-09 Sat>");
+        assert_eq!(
+            text[1],
+            "DEADLINE: <2022-05-01 Sun> SCHEDULED: <2022-04-09 Sat>"
+        );
+    }
+
+    #[test]
+    fn add_ids_only_to_cards_without_one() {
+        let text = "* Notes\n* First :card:\n** Answer\n* Second :drill:\n:PROPERTIES:\n:ID: kept\n:END:\n";
+        let result = add_missing_ids(text).unwrap();
+        let result_lines = lines(&result);
+        assert!(result.ends_with('\n'));
+        assert_eq!(get_property(&result_lines, 0, "ID"), None);
+        assert!(get_property(&result_lines, 1, "ID").is_some());
+        assert_eq!(subheadline_titles(&result_lines, 1), vec!["Answer"]);
+        assert_eq!(
+            get_property(&result_lines, 6, "ID").as_deref(),
+            Some("kept")
+        );
+        assert_eq!(add_missing_ids(&result), None);
+    }
+}
@@ -0,0 +1,301 @@
+//! A local HTTP server with a JSON API, so editors can show the due cards and
+//! send grades without the window.
+//!
+//! Every request needs the token in an `Authorization: Bearer <token>`
+//! header. The endpoints are:
+//!
+//! - `GET /due?limit=N` the number of due cards and the first `N` of them
+//! - `GET /cards/<id>` a card with the content of its document
+//! - `POST /cards/<id>/grade` schedules a card with `{"grade": 1-4}`
+//! - `POST /import` imports a file or folder with `{"path": "..."}`, after a
+//!   backup of the database
+
+use std::{io::Read, path::Path};
+
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    database::Database,
+    import,
+    org::FlashCard,
+    sr::{Grade, Review, Scheduler},
+};
+
+/// The default address, reachable from this machine only.
+pub const DEFAULT_BIND: &str = "127.0.0.1:7878";
+
+/// Requests with a larger body are refused.
+const MAX_BODY: u64 = 64 * 1024;
+
+pub struct Options {
+    pub bind: String,
+    pub token: String,
+    pub scheduler: Box<dyn Scheduler>,
+    /// The globs of the files imported from a folder.
+    pub include: String,
+    pub exclude: String,
+}
+
+/// An error answered with its status code.
+struct ApiError(u16, String);
+
+type ApiResult = Result<Value, ApiError>;
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        ApiError(400, message.into())
+    }
+
+    fn not_found() -> Self {
+        ApiError(404, String::from("not found"))
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        ApiError(500, err.to_string())
+    }
+}
+
+/// Serves requests until the process is stopped. The requests are handled
+/// one after another, so they never write to the database at the same time.
+pub fn serve(db: &mut Database, options: &Options) -> Result<(), String> {
+    let server = Server::http(options.bind.as_str()).map_err(|err| err.to_string())?;
+    println!("Listening on http://{}", options.bind);
+    for mut request in server.incoming_requests() {
+        let (status, body) = match handle(db, options, &mut request) {
+            Ok(body) => (200, body),
+            Err(ApiError(status, message)) => (status, json!({ "error": message })),
+        };
+        let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(err) = request.respond(response) {
+            println!("Error: {:?}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Compares every byte, so the time taken doesn't tell how much of a guessed
+/// token is right.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    request
+        .headers()
+        .iter()
+        .filter(|header| header.field.equiv("Authorization"))
+        .filter_map(|header| header.value.as_str().strip_prefix("Bearer "))
+        .any(|given| same_token(given.trim(), token))
+}
+
+fn handle(db: &mut Database, options: &Options, request: &mut Request) -> ApiResult {
+    if !is_authorized(request, &options.token) {
+        return Err(ApiError(401, String::from("missing or wrong token")));
+    }
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["due"]) => due(db, query),
+        (Method::Get, ["cards", id]) => card(db, parse_id(id)?),
+        (Method::Post, ["cards", id, "grade"]) => {
+            let id = parse_id(id)?;
+            let body = read_body(request)?;
+            grade(db, options, id, &body)
+        }
+        (Method::Post, ["import"]) => {
+            let body = read_body(request)?;
+            import(db, options, &body)
+        }
+        (_, ["due"] | ["cards", _] | ["cards", _, "grade"] | ["import"]) => {
+            Err(ApiError(405, String::from("method not allowed")))
+        }
+        _ => Err(ApiError::not_found()),
+    }
+}
+
+fn parse_id(id: &str) -> Result<i64, ApiError> {
+    id.parse()
+        .map_err(|_| ApiError::bad_request(format!("{} is not a card ID", id)))
+}
+
+fn read_body(request: &mut Request) -> ApiResult {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body)
+        .map_err(|err| ApiError::bad_request(err.to_string()))?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(ApiError(413, String::from("the body is too large")));
+    }
+    serde_json::from_str(&body).map_err(|err| ApiError::bad_request(err.to_string()))
+}
+
+fn card_json(card: &FlashCard) -> Value {
+    let stats = card.get_stats();
+    json!({
+        "id": card.get_id(),
+        "uid": card.get_uid(),
+        "document": card.get_doc_id(),
+        "question": card.get_questions(),
+        "answer": card.get_answers(),
+        "tags": card.get_tags(),
+        "interval": stats.interval,
+        "repetitions": stats.num_reps,
+        "last_review": stats.last_review.map(|date| date.to_string()),
+        "scheduled": card.get_scheduled().map(|date| date.to_string()),
+    })
+}
+
+fn due(db: &Database, query: &str) -> ApiResult {
+    let mut limit = 50;
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        if key == "limit" {
+            // A limit of 0 only counts the due cards.
+            limit = value
+                .parse::<u32>()
+                .ok()
+                .and_then(|limit| i32::try_from(limit).ok())
+                .ok_or_else(|| ApiError::bad_request(format!("{} is not a limit", value)))?;
+        }
+    }
+    let count: i64 = db.due_counts()?.iter().map(|(_, count)| count).sum();
+    let cards: Vec<Value> = db
+        .get_due_flashcards(limit)?
+        .iter()
+        .map(card_json)
+        .collect();
+    Ok(json!({ "count": count, "cards": cards }))
+}
+
+fn card(db: &Database, id: i64) -> ApiResult {
+    let card = db.get_flashcard(id)?.ok_or_else(ApiError::not_found)?;
+    let mut value = card_json(&card);
+    if let Some(document) = db.load_data(card.get_doc_id())?.first() {
+        value["context"] = json!({
+            "id": document.get_id(),
+            "title": document.get_title(),
+            "content": document.get_contents(),
+        });
+    }
+    Ok(value)
+}
+
+/// Reads a grade as its number from 1 to 4 or as its name.
+fn parse_grade(value: &Value) -> Option<Grade> {
+    match value {
+        Value::Number(number) => {
+            let index = number.as_u64()?.checked_sub(1)?;
+            Grade::ALL.get(index as usize).copied()
+        }
+        Value::String(name) => Grade::ALL
+            .iter()
+            .find(|grade| grade.label().eq_ignore_ascii_case(name))
+            .copied(),
+        _ => None,
+    }
+}
+
+fn grade(db: &mut Database, options: &Options, id: i64, body: &Value) -> ApiResult {
+    let grade = parse_grade(&body["grade"])
+        .ok_or_else(|| ApiError::bad_request("the grade must be 1 to 4 or its name"))?;
+    let mut card = db.get_flashcard(id)?.ok_or_else(ApiError::not_found)?;
+
+    let today = Utc::now().date();
+    let previous_interval = card.get_stats().interval;
+    options
+        .scheduler
+        .repeat(card.get_stats_mut(), grade, today.naive_local());
+    db.update_flashcards(&vec![card.clone()]);
+    db.insert_reviews(&vec![Review {
+        card_id: id,
+        reviewed_at: Utc::now(),
+        grade,
+        previous_interval,
+        new_interval: card.get_stats().interval,
+        ease: card.get_stats().difficultly,
+        duration: body["duration"].as_i64().unwrap_or(0),
+    }])?;
+
+    // The same date as the one written by `update_flashcards`.
+    let scheduled = (today + Duration::days(card.get_stats().interval)).naive_local();
+    card.set_scheduled(Some(scheduled));
+    Ok(card_json(&card))
+}
+
+fn import(db: &Database, options: &Options, body: &Value) -> ApiResult {
+    let root = body["path"]
+        .as_str()
+        .map(Path::new)
+        .ok_or_else(|| ApiError::bad_request("the path is missing"))?;
+    if !root.exists() {
+        return Err(ApiError::bad_request(format!(
+            "{} does not exist",
+            root.display()
+        )));
+    }
+    let paths = if root.is_dir() {
+        let include = body["include"].as_str().unwrap_or(&options.include);
+        let exclude = body["exclude"].as_str().unwrap_or(&options.exclude);
+        import::find_files(root, include, exclude, false)
+    } else {
+        vec![root.to_path_buf()]
+    };
+
+    db.back_up(db.backup_count())?;
+    let mut files = vec![];
+    let mut unchanged = 0;
+    for path in paths {
+        match import::import_file(db, &path, false) {
+            Ok(Some(summary)) => files.push(json!({
+                "path": path.display().to_string(),
+                "summary": summary,
+            })),
+            Ok(None) => unchanged += 1,
+            Err(err) => files.push(json!({
+                "path": path.display().to_string(),
+                "error": err,
+            })),
+        }
+    }
+    Ok(json!({ "files": files, "unchanged": unchanged }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rusqlite::Connection;
+
+    use super::*;
+
+    #[test]
+    fn due_limit() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        Database::migrate(&mut conn, Path::new("test.db3")).unwrap();
+        let db = Database { conn };
+
+        let counted = due(&db, "limit=0").map_err(|err| err.1).unwrap();
+        assert_eq!(counted["count"], 0);
+        for query in ["limit=-1", "limit=abc", "limit=4294967296"] {
+            assert!(
+                matches!(due(&db, query), Err(ApiError(400, _))),
+                "{}",
+                query
+            );
+        }
+    }
+}
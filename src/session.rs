@@ -0,0 +1,175 @@
+//! A review session over the due cards. It is shared by the window, the
+//! command line and the terminal, which only differ in how a card is shown.
+
+use std::{collections::HashMap, time::Instant};
+
+use chrono::Utc;
+use rand::prelude::*;
+
+use crate::{
+    database::{Database, DeckKey},
+    org::FlashCard,
+    sr::{Grade, Review, Scheduler},
+};
+
+pub struct Session {
+    cards: Vec<FlashCard>,
+    active_card: usize,
+    done: bool,
+    /// Whether each card was recalled in this session.
+    stats: HashMap<usize, bool>,
+    grades: HashMap<usize, Grade>,
+    reviews: Vec<Review>,
+    shown_at: Instant,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            cards: vec![],
+            active_card: 0,
+            done: false,
+            stats: HashMap::new(),
+            grades: HashMap::new(),
+            reviews: vec![],
+            shown_at: Instant::now(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cards.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Selects the due cards of the decks, or of every deck without any,
+    /// capped by the number of cards in settings. The state of the cards
+    /// fetched before is dropped, as the indices now point to other cards.
+    pub fn fetch(&mut self, db: &Database, num_cards: i32, decks: &[DeckKey]) {
+        if let Ok(result) = db.get_due_deck_flashcards(num_cards, decks) {
+            self.cards = result;
+        }
+        self.stats = (0..self.cards.len()).map(|id| (id, false)).collect();
+        self.grades.clear();
+        self.reviews.clear();
+        self.active_card = 0;
+        self.done = false;
+        self.shown_at = Instant::now();
+    }
+
+    /// The index of the card that is shown.
+    pub fn active(&self) -> usize {
+        self.active_card
+    }
+
+    pub fn card(&self) -> &FlashCard {
+        &self.cards[self.active_card]
+    }
+
+    pub fn next(&mut self, rng: &mut ThreadRng) {
+        let vec: Vec<usize> = self
+            .stats
+            .iter()
+            .filter(|(_, y)| !*y)
+            .map(|(x, _)| *x)
+            .collect();
+
+        if vec.is_empty() {
+            self.done = true;
+            return;
+        }
+
+        let dist = rand::distributions::Uniform::new_inclusive(0, vec.len() - 1);
+        self.active_card = vec[rng.sample(dist)];
+        self.shown_at = Instant::now();
+    }
+
+    pub fn reset(&mut self) {
+        if self.done {
+            for (_, value) in self.stats.iter_mut() {
+                *value = false;
+            }
+            self.grades.clear();
+            self.reviews.clear();
+            self.cards = vec![];
+            self.done = false;
+            self.active_card = 0;
+        }
+        self.shown_at = Instant::now();
+    }
+
+    /// Every answer is logged, but only the first answer of a session updates
+    /// the stats. A card answered with `Again` stays in the session until it
+    /// is recalled.
+    pub fn update_and_next(
+        &mut self,
+        grade: Grade,
+        scheduler: &dyn Scheduler,
+        rng: &mut ThreadRng,
+    ) {
+        let card = &mut self.cards[self.active_card];
+        let previous_interval = card.get_stats().interval;
+        if !self.grades.contains_key(&self.active_card) {
+            self.grades.insert(self.active_card, grade);
+            scheduler.repeat(card.get_stats_mut(), grade, Utc::now().date().naive_local());
+        }
+        self.reviews.push(Review {
+            card_id: card.get_id(),
+            reviewed_at: Utc::now(),
+            grade,
+            previous_interval,
+            new_interval: card.get_stats().interval,
+            ease: card.get_stats().difficultly,
+            duration: self.shown_at.elapsed().as_millis() as i64,
+        });
+        if grade.is_correct() {
+            self.stats.entry(self.active_card).and_modify(|x| *x = true);
+        }
+        self.next(rng);
+    }
+
+    /// Saves the answered cards and the reviews. A session that is left
+    /// early keeps the cards that were not answered due.
+    pub fn save_to_database(&mut self, db: &mut Database) {
+        let cards: Vec<FlashCard> = self.reviewed_cards().into_iter().cloned().collect();
+        db.update_flashcards(&cards);
+        if let Err(err) = db.insert_reviews(&self.reviews) {
+            println!("Error: {:?}", err);
+        }
+        self.reviews.clear();
+    }
+
+    /// The cards that were answered in this session.
+    pub fn reviewed_cards(&self) -> Vec<&FlashCard> {
+        self.grades.keys().map(|idx| &self.cards[*idx]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sr::Sm2;
+
+    #[test]
+    fn every_answer_is_logged() {
+        let mut session = Session::new();
+        session.cards = vec![FlashCard::new()];
+        session.stats.insert(0, false);
+        let mut rng = rand::thread_rng();
+
+        session.update_and_next(Grade::Again, &Sm2, &mut rng);
+        assert!(!session.is_done());
+        session.update_and_next(Grade::Good, &Sm2, &mut rng);
+        assert!(session.is_done());
+
+        let grades: Vec<Grade> = session.reviews.iter().map(|review| review.grade).collect();
+        assert_eq!(grades, [Grade::Again, Grade::Good]);
+        // The stats are only updated by the first answer.
+        assert_eq!(session.card().get_stats().num_reps, 0);
+        assert_eq!(session.card().get_stats().interval, 1);
+        assert_eq!(session.reviews[1].previous_interval, 1);
+        assert_eq!(session.reviewed_cards().len(), 1);
+    }
+}
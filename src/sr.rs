@@ -1,8 +1,121 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// The answer given for a card. Each answer maps to a SM-2 quality score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grade {
+    Again,
+    Hard,
+    Good,
+    Easy,
+}
+
+impl Grade {
+    pub const ALL: [Grade; 4] = [Grade::Again, Grade::Hard, Grade::Good, Grade::Easy];
+
+    /// SM-2 quality of the response, ranging from 0 to 5.
+    pub fn quality(&self) -> i8 {
+        match self {
+            Grade::Again => 1,
+            Grade::Hard => 3,
+            Grade::Good => 4,
+            Grade::Easy => 5,
+        }
+    }
+
+    pub fn from_quality(quality: i8) -> Self {
+        match quality {
+            i8::MIN..=2 => Grade::Again,
+            3 => Grade::Hard,
+            4 => Grade::Good,
+            _ => Grade::Easy,
+        }
+    }
+
+    /// Anything except `Again` counts as a correct response.
+    pub fn is_correct(&self) -> bool {
+        self.quality() >= 3
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Grade::Again => "Again",
+            Grade::Hard => "Hard",
+            Grade::Good => "Good",
+            Grade::Easy => "Easy",
+        }
+    }
+}
+
+/// A single answer given during a session. These are kept in the review log
+/// so that the history of a card is never overwritten.
+#[derive(Debug, Clone)]
+pub struct Review {
+    pub card_id: i64,
+    pub reviewed_at: DateTime<Utc>,
+    pub grade: Grade,
+    pub previous_interval: i64,
+    pub new_interval: i64,
+    pub ease: f64,
+    /// Time taken to answer in milliseconds.
+    pub duration: i64,
+}
+
+/// Spaced repetition algorithms that can schedule a collection.
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Algorithm {
+    #[default]
+    Sm2,
+    Fsrs,
+}
+
+impl Algorithm {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Algorithm::Sm2 => "SM-2",
+            Algorithm::Fsrs => "FSRS",
+        }
+    }
+
+    /// The name on the command line and in the database.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sm2 => "sm2",
+            Algorithm::Fsrs => "fsrs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        [Algorithm::Sm2, Algorithm::Fsrs]
+            .into_iter()
+            .find(|algorithm| algorithm.name() == name)
+    }
+
+    /// Creates the scheduler. The weights are only used by FSRS.
+    pub fn scheduler(&self, weights: &[f64; 17]) -> Box<dyn Scheduler> {
+        match self {
+            Algorithm::Sm2 => Box::new(Sm2),
+            Algorithm::Fsrs => Box::new(Fsrs::new(*weights)),
+        }
+    }
+}
+
+/// A scheduler updates the stats of a card after it has been answered. The
+/// next interval is always written to `Stats::interval`.
+pub trait Scheduler {
+    fn repeat(&self, stats: &mut Stats, grade: Grade, today: NaiveDate);
+}
+
 #[derive(Debug, Clone)]
 pub struct Stats {
     pub interval: i64,
     pub num_reps: i16,
     pub difficultly: f64,
+    /// FSRS memory stability in days. Zero means there is no FSRS state yet.
+    pub stability: f64,
+    /// FSRS difficulty ranging from 1 to 10.
+    pub recall_difficulty: f64,
+    pub last_review: Option<NaiveDate>,
 }
 
 impl Stats {
@@ -11,28 +124,264 @@ impl Stats {
             interval: 0,
             num_reps: 0,
             difficultly: 1.3,
+            stability: 0.0,
+            recall_difficulty: 0.0,
+            last_review: None,
+        }
+    }
+
+    /// Derives the FSRS state from the SM-2 stats. The interval is used as
+    /// the stability, and the ease is mapped onto the difficulty range.
+    pub fn seed_fsrs(&mut self) {
+        if self.num_reps == 0 && self.interval == 0 {
+            // A card that is never reviewed starts as a new card.
+            return;
         }
+        self.stability = (self.interval as f64).max(1.0);
+        self.recall_difficulty = (5.0 + (2.5 - self.difficultly) * 4.0).clamp(1.0, 10.0);
     }
+}
 
-    pub fn repeat(&mut self, grade: i8) {
+/// The SM-2 algorithm. Details can be found at
+/// https://www.supermemo.com/en/archives1990-2015/english/ol/sm2
+pub struct Sm2;
+
+impl Scheduler for Sm2 {
+    fn repeat(&self, stats: &mut Stats, grade: Grade, today: NaiveDate) {
         /* Handle correct response */
-        if grade >= 2 {
-            match self.num_reps {
-                0 => self.interval = 1,
-                1 => self.interval = 4,
-                _ => self.interval = (self.interval as f64 * self.difficultly).round() as i64, // Confirm rounding later.
+        if grade.is_correct() {
+            match stats.num_reps {
+                0 => stats.interval = 1,
+                1 => stats.interval = 4,
+                _ => stats.interval = (stats.interval as f64 * stats.difficultly).round() as i64, // Confirm rounding later.
             };
-            self.num_reps += 1;
+            stats.num_reps += 1;
         } else
         /* Incorrect Reponse */
         {
-            self.num_reps = 0;
-            self.interval = 1;
+            stats.num_reps = 0;
+            stats.interval = 1;
+        }
+        let diff = 5.0 - grade.quality() as f64;
+        stats.difficultly += 0.1 - diff * (0.08 + diff * 0.02);
+        if stats.difficultly <= 1.3 {
+            stats.difficultly = 1.3;
+        }
+        // The FSRS state is stale now. It is seeded again if FSRS is used.
+        stats.stability = 0.0;
+        stats.last_review = Some(today);
+    }
+}
+
+const DECAY: f64 = -0.5;
+const FACTOR: f64 = 19.0 / 81.0;
+
+/// Default FSRS-4.5 weights.
+pub const FSRS_WEIGHTS: [f64; 17] = [
+    0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975, 0.031, 1.6474, 0.1367, 1.0461,
+    2.1072, 0.0793, 0.3246, 1.587, 0.2272, 2.8755,
+];
+
+/// The Free Spaced Repetition Scheduler. It models the memory of a card with
+/// stability, difficulty and retrievability.
+pub struct Fsrs {
+    pub weights: [f64; 17],
+    /// The probability of recall at which a card is due.
+    pub retention: f64,
+}
+
+impl Default for Fsrs {
+    fn default() -> Self {
+        Self {
+            weights: FSRS_WEIGHTS,
+            retention: 0.9,
+        }
+    }
+}
+
+impl Fsrs {
+    pub fn new(weights: [f64; 17]) -> Self {
+        Self {
+            weights,
+            ..Default::default()
+        }
+    }
+
+    /// FSRS rating ranging from 1 (Again) to 4 (Easy).
+    fn rating(grade: Grade) -> f64 {
+        match grade {
+            Grade::Again => 1.0,
+            Grade::Hard => 2.0,
+            Grade::Good => 3.0,
+            Grade::Easy => 4.0,
+        }
+    }
+
+    /// Probability of recall after `elapsed` days.
+    pub fn retrievability(elapsed: f64, stability: f64) -> f64 {
+        (1.0 + FACTOR * elapsed / stability).powf(DECAY)
+    }
+
+    fn init_stability(&self, rating: f64) -> f64 {
+        self.weights[rating as usize - 1].max(0.1)
+    }
+
+    fn init_difficulty(&self, rating: f64) -> f64 {
+        (self.weights[4] - (rating - 3.0) * self.weights[5]).clamp(1.0, 10.0)
+    }
+
+    fn next_difficulty(&self, difficulty: f64, rating: f64) -> f64 {
+        let next = difficulty - self.weights[6] * (rating - 3.0);
+        // Mean reversion towards the initial difficulty of a good answer.
+        (self.weights[7] * self.init_difficulty(3.0) + (1.0 - self.weights[7]) * next)
+            .clamp(1.0, 10.0)
+    }
+
+    fn recall_stability(&self, difficulty: f64, stability: f64, r: f64, rating: f64) -> f64 {
+        let w = &self.weights;
+        let hard_penalty = if rating == 2.0 { w[15] } else { 1.0 };
+        let easy_bonus = if rating == 4.0 { w[16] } else { 1.0 };
+        stability
+            * (1.0
+                + w[8].exp()
+                    * (11.0 - difficulty)
+                    * stability.powf(-w[9])
+                    * (((1.0 - r) * w[10]).exp() - 1.0)
+                    * hard_penalty
+                    * easy_bonus)
+    }
+
+    fn forget_stability(&self, difficulty: f64, stability: f64, r: f64) -> f64 {
+        let w = &self.weights;
+        w[11]
+            * difficulty.powf(-w[12])
+            * ((stability + 1.0).powf(w[13]) - 1.0)
+            * ((1.0 - r) * w[14]).exp()
+    }
+
+    /// Updates the memory state after an answer given `elapsed` days after
+    /// the previous review.
+    pub fn step(&self, stats: &mut Stats, grade: Grade, elapsed: f64) {
+        let rating = Fsrs::rating(grade);
+        if stats.stability <= 0.0 {
+            stats.stability = self.init_stability(rating);
+            stats.recall_difficulty = self.init_difficulty(rating);
+            return;
+        }
+        let r = Fsrs::retrievability(elapsed, stats.stability);
+        stats.stability = if grade.is_correct() {
+            self.recall_stability(stats.recall_difficulty, stats.stability, r, rating)
+        } else {
+            self.forget_stability(stats.recall_difficulty, stats.stability, r)
+        }
+        .max(0.1);
+        stats.recall_difficulty = self.next_difficulty(stats.recall_difficulty, rating);
+    }
+
+    fn next_interval(&self, stability: f64) -> i64 {
+        let interval = stability / FACTOR * (self.retention.powf(1.0 / DECAY) - 1.0);
+        (interval.round() as i64).max(1)
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn repeat(&self, stats: &mut Stats, grade: Grade, today: NaiveDate) {
+        if stats.stability <= 0.0 {
+            stats.seed_fsrs();
         }
-        let diff = 4.0 - grade as f64;
-        self.difficultly = self.difficultly + (0.1 - diff * (0.08 + diff * 0.02));
-        if self.difficultly <= 1.3 {
-            self.difficultly = 1.3;
+        let elapsed = match stats.last_review {
+            Some(date) => (today - date).num_days(),
+            None => stats.interval,
+        };
+        self.step(stats, grade, elapsed.max(0) as f64);
+        if grade.is_correct() {
+            stats.num_reps += 1;
+        } else {
+            stats.num_reps = 0;
         }
+        stats.interval = self.next_interval(stats.stability);
+        stats.last_review = Some(today);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 1, n)
+    }
+
+    #[test]
+    fn sm2_intervals() {
+        let mut stats = Stats::new();
+        Sm2.repeat(&mut stats, Grade::Good, day(1));
+        assert_eq!((stats.interval, stats.num_reps), (1, 1));
+        Sm2.repeat(&mut stats, Grade::Good, day(2));
+        assert_eq!((stats.interval, stats.num_reps), (4, 2));
+        Sm2.repeat(&mut stats, Grade::Easy, day(6));
+        assert_eq!(stats.interval, 5);
+        assert!((stats.difficultly - 1.4).abs() < 1e-9);
+        Sm2.repeat(&mut stats, Grade::Easy, day(11));
+        assert_eq!(stats.interval, 7);
+        assert_eq!(stats.last_review, Some(day(11)));
+
+        Sm2.repeat(&mut stats, Grade::Again, day(19));
+        assert_eq!((stats.interval, stats.num_reps), (1, 0));
+        // The ease never drops below 1.3.
+        assert!((stats.difficultly - 1.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fsrs_first_answer() {
+        let fsrs = Fsrs::default();
+        let first = |grade| {
+            let mut stats = Stats::new();
+            fsrs.repeat(&mut stats, grade, day(1));
+            stats
+        };
+        // At a retention of 0.9 the interval equals the stability.
+        assert_eq!(first(Grade::Again).interval, 1);
+        assert_eq!(first(Grade::Hard).interval, 1);
+        assert_eq!(first(Grade::Good).interval, 4);
+        assert_eq!(first(Grade::Easy).interval, 14);
+        assert!((first(Grade::Good).recall_difficulty - 5.1618).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fsrs_intervals() {
+        let fsrs = Fsrs::default();
+        let mut stats = Stats::new();
+        fsrs.repeat(&mut stats, Grade::Good, day(1));
+        fsrs.repeat(&mut stats, Grade::Good, day(5));
+        assert_eq!(stats.num_reps, 2);
+        assert!(stats.interval > 4);
+        let good = stats.interval;
+
+        let mut hard = Stats::new();
+        fsrs.repeat(&mut hard, Grade::Good, day(1));
+        fsrs.repeat(&mut hard, Grade::Hard, day(5));
+        assert!(hard.interval < good);
+
+        let stability = stats.stability;
+        fsrs.repeat(&mut stats, Grade::Again, day(20));
+        assert_eq!(stats.num_reps, 0);
+        assert!(stats.stability < stability);
+        assert!(stats.interval < good);
+    }
+
+    #[test]
+    fn fsrs_is_seeded_from_sm2() {
+        let mut stats = Stats::new();
+        stats.interval = 10;
+        stats.num_reps = 3;
+        stats.difficultly = 2.5;
+        stats.seed_fsrs();
+        assert_eq!(stats.stability, 10.0);
+        assert_eq!(stats.recall_difficulty, 5.0);
+
+        let mut stats = Stats::new();
+        stats.seed_fsrs();
+        assert_eq!(stats.stability, 0.0);
     }
 }
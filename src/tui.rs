@@ -0,0 +1,451 @@
+//! The review in the terminal, for sessions over SSH or in tmux where the
+//! window can't be opened. It runs the same session as the window and shows
+//! the org text of the cards and their context with terminal styles.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event as TermEvent, KeyCode, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Color, ContentStyle, PrintStyledContent, StyledContent},
+    terminal::{self, ClearType},
+};
+use orgize::{Element, Event, Org};
+use rand::prelude::*;
+
+use crate::{
+    database::Database,
+    session::Session,
+    sr::{Grade, Scheduler},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Plain,
+    Heading,
+    Bold,
+    Italic,
+    Underline,
+    Strike,
+    Code,
+    Link,
+    Dim,
+}
+
+impl Style {
+    fn apply(self, text: String) -> StyledContent<String> {
+        let mut style = ContentStyle::new();
+        match self {
+            Style::Plain => {}
+            Style::Heading => {
+                style.foreground_color = Some(Color::Cyan);
+                style.attributes.set(Attribute::Bold);
+            }
+            Style::Bold => style.attributes.set(Attribute::Bold),
+            Style::Italic => style.attributes.set(Attribute::Italic),
+            Style::Underline => style.attributes.set(Attribute::Underlined),
+            Style::Strike => style.attributes.set(Attribute::CrossedOut),
+            Style::Code => style.foreground_color = Some(Color::Yellow),
+            Style::Link => {
+                style.foreground_color = Some(Color::Blue);
+                style.attributes.set(Attribute::Underlined);
+            }
+            Style::Dim => style.attributes.set(Attribute::Dim),
+        }
+        style.apply(text)
+    }
+}
+
+/// A line of styled pieces of text.
+type Line = Vec<(String, Style)>;
+
+/// Lays out org text into lines that fit the width of the terminal.
+struct Renderer {
+    width: usize,
+    lines: Vec<Line>,
+    /// The text of the paragraph or title that is laid out.
+    pieces: Line,
+    styles: Vec<Style>,
+    /// The indentation of the nested list items and quotes.
+    indents: Vec<usize>,
+    bullet: Option<String>,
+    lists: usize,
+    /// The depth of the drawers, whose content is hidden.
+    hidden: usize,
+}
+
+impl Renderer {
+    fn style(&self) -> Style {
+        *self.styles.last().unwrap_or(&Style::Plain)
+    }
+
+    fn indent(&self) -> usize {
+        self.indents.last().copied().unwrap_or(0)
+    }
+
+    fn push(&mut self, text: &str, style: Style) {
+        self.pieces.push((text.to_owned(), style));
+    }
+
+    fn blank(&mut self) {
+        if self.lines.last().is_some_and(|line| !line.is_empty()) {
+            self.lines.push(vec![]);
+        }
+    }
+
+    /// Wraps the pieces into lines. A word can be made of pieces with
+    /// different styles, like `*bold*,`.
+    fn flush(&mut self) {
+        let mut words: Vec<Line> = vec![];
+        let mut word: Line = vec![];
+        for (text, style) in std::mem::take(&mut self.pieces) {
+            let mut part = String::new();
+            for c in text.chars() {
+                if !c.is_whitespace() {
+                    part.push(c);
+                    continue;
+                }
+                if !part.is_empty() {
+                    word.push((std::mem::take(&mut part), style));
+                }
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            if !part.is_empty() {
+                word.push((part, style));
+            }
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+
+        let bullet = self.bullet.take();
+        if words.is_empty() && bullet.is_none() {
+            return;
+        }
+        let indent = self.indent();
+        let margin = match bullet {
+            Some(bullet) => format!("{:>width$}", bullet, width = indent),
+            None => " ".repeat(indent),
+        };
+        let mut line = vec![(margin, Style::Plain)];
+        let mut len = indent;
+        let mut empty = true;
+        for word in words {
+            let word_len: usize = word.iter().map(|(text, _)| text.chars().count()).sum();
+            if !empty && len + 1 + word_len > self.width {
+                let margin = (" ".repeat(indent), Style::Plain);
+                self.lines.push(std::mem::replace(&mut line, vec![margin]));
+                len = indent;
+                empty = true;
+            }
+            if !empty {
+                line.push((String::from(" "), Style::Plain));
+                len += 1;
+            }
+            len += word_len;
+            line.extend(word);
+            empty = false;
+        }
+        self.lines.push(line);
+    }
+
+    /// Blocks keep their lines as they are.
+    fn block(&mut self, contents: &str) {
+        self.flush();
+        self.blank();
+        let margin = " ".repeat(self.indent() + 2);
+        for line in contents.trim_end().lines() {
+            let line = format!("{}{}", margin, line.replace('\t', "    "));
+            self.lines.push(vec![(line, Style::Code)]);
+        }
+        self.blank();
+    }
+}
+
+/// Lays out org text. `base` is the style of the text without markup.
+fn render(text: &str, width: usize, base: Style) -> Vec<Line> {
+    let org = Org::parse(text);
+    let mut renderer = Renderer {
+        width: width.max(20),
+        lines: vec![],
+        pieces: vec![],
+        styles: vec![base],
+        indents: vec![],
+        bullet: None,
+        lists: 0,
+        hidden: 0,
+    };
+    let r = &mut renderer;
+    for event in org.iter() {
+        match event {
+            Event::Start(Element::Drawer(_)) => r.hidden += 1,
+            Event::End(Element::Drawer(_)) => r.hidden -= 1,
+            _ if r.hidden > 0 => {}
+
+            Event::Start(Element::Title(_)) => {
+                r.flush();
+                r.blank();
+                r.styles.push(Style::Heading);
+            }
+            Event::End(Element::Title(_)) => {
+                r.flush();
+                r.styles.pop();
+            }
+            Event::End(Element::Paragraph { .. }) => {
+                r.flush();
+                if r.lists == 0 {
+                    r.blank();
+                }
+            }
+            Event::Start(Element::List(_)) => {
+                r.flush();
+                r.lists += 1;
+            }
+            Event::End(Element::List(_)) => {
+                r.lists -= 1;
+                if r.lists == 0 {
+                    r.blank();
+                }
+            }
+            Event::Start(Element::ListItem(item)) => {
+                r.flush();
+                let bullet = if item.ordered {
+                    format!("{} ", item.bullet.trim())
+                } else {
+                    String::from("• ")
+                };
+                r.indents.push(item.indent + bullet.chars().count());
+                r.bullet = Some(bullet);
+            }
+            Event::End(Element::ListItem(_)) => {
+                r.flush();
+                r.indents.pop();
+            }
+            Event::Start(Element::QuoteBlock(_)) => {
+                r.flush();
+                r.indents.push(r.indent() + 2);
+                r.styles.push(Style::Italic);
+            }
+            Event::End(Element::QuoteBlock(_)) => {
+                r.flush();
+                r.indents.pop();
+                r.styles.pop();
+                r.blank();
+            }
+            Event::Start(Element::SourceBlock(block)) => r.block(&block.contents),
+            Event::Start(Element::ExampleBlock(block)) => r.block(&block.contents),
+            Event::Start(Element::FixedWidth(block)) => r.block(&block.value),
+            Event::End(Element::TableCell(_)) => r.push(" |", Style::Dim),
+            Event::End(Element::TableRow(_)) => r.flush(),
+            Event::End(Element::Table(_)) => r.blank(),
+
+            Event::Start(Element::Text { value }) => {
+                let style = r.style();
+                r.push(value, style);
+            }
+            Event::Start(Element::Code { value } | Element::Verbatim { value }) => {
+                r.push(value, Style::Code)
+            }
+            Event::Start(Element::Link(link)) => {
+                r.push(link.desc.as_ref().unwrap_or(&link.path), Style::Link)
+            }
+            Event::Start(Element::Bold) => r.styles.push(Style::Bold),
+            Event::Start(Element::Italic) => r.styles.push(Style::Italic),
+            Event::Start(Element::Underline) => r.styles.push(Style::Underline),
+            Event::Start(Element::Strike) => r.styles.push(Style::Strike),
+            Event::End(Element::Bold | Element::Italic | Element::Underline | Element::Strike) => {
+                r.styles.pop();
+            }
+            _ => {}
+        }
+    }
+    renderer.flush();
+    let mut lines = renderer.lines;
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Leaves the raw mode and the alternate screen when it is dropped, also on
+/// an error or a panic.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        // Created first, so the raw mode is left if the screen can't be entered.
+        let screen = Screen;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Review {
+    session: Session,
+    reveal: bool,
+    context: bool,
+    scroll: usize,
+    /// The title and the content of the documents, by their ID.
+    documents: HashMap<i64, (String, String)>,
+}
+
+impl Review {
+    fn draw(&mut self, db: &Database, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let text_width = width.saturating_sub(4);
+        let card = self.session.card();
+        let (title, content) = self
+            .documents
+            .entry(card.get_doc_id())
+            .or_insert_with(|| match db.load_data(card.get_doc_id()) {
+                Ok(documents) if !documents.is_empty() => (
+                    documents[0].get_title().to_owned(),
+                    documents[0].get_contents().to_owned(),
+                ),
+                _ => Default::default(),
+            });
+
+        let lines = if self.context {
+            render(content, text_width, Style::Plain)
+        } else {
+            let mut lines = render(card.get_questions(), text_width, Style::Bold);
+            if self.reveal {
+                lines.push(vec![]);
+                lines.push(vec![("─".repeat(text_width.min(40)), Style::Dim)]);
+                lines.push(vec![]);
+                lines.extend(render(card.get_answers(), text_width, Style::Plain));
+            }
+            lines
+        };
+        let body = height.saturating_sub(4);
+        self.scroll = self.scroll.min(lines.len().saturating_sub(body));
+
+        let progress = format!(
+            "{}/{}",
+            self.session.reviewed_cards().len(),
+            self.session.len()
+        );
+        let help = if self.context {
+            String::from("↑↓ scroll  c card  q quit")
+        } else if self.reveal {
+            let grades: Vec<String> = Grade::ALL
+                .iter()
+                .enumerate()
+                .map(|(idx, grade)| format!("{} {}", idx + 1, grade.label()))
+                .collect();
+            format!("{}  c context  q quit", grades.join("  "))
+        } else {
+            String::from("space answer  c context  q quit")
+        };
+
+        queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(1, 0))?;
+        queue!(out, PrintStyledContent(Style::Heading.apply(title.clone())))?;
+        let column = width.saturating_sub(progress.len() + 1) as u16;
+        queue!(
+            out,
+            cursor::MoveTo(column, 0),
+            PrintStyledContent(Style::Dim.apply(progress))
+        )?;
+        for (row, line) in lines.iter().skip(self.scroll).take(body).enumerate() {
+            queue!(out, cursor::MoveTo(2, row as u16 + 2))?;
+            let mut room = width.saturating_sub(2);
+            for (text, style) in line {
+                let text: String = text.chars().take(room).collect();
+                room -= text.chars().count();
+                queue!(out, PrintStyledContent(style.apply(text)))?;
+            }
+        }
+        queue!(
+            out,
+            cursor::MoveTo(1, height.saturating_sub(1) as u16),
+            PrintStyledContent(Style::Dim.apply(help))
+        )?;
+        out.flush()
+    }
+    /// Shows the cards and grades them until the session is done or left.
+    fn answer(
+        &mut self,
+        db: &Database,
+        scheduler: &dyn Scheduler,
+        rng: &mut ThreadRng,
+    ) -> io::Result<()> {
+        let mut out = io::stdout();
+        while !self.session.is_done() {
+            self.draw(db, &mut out)?;
+            // Any other event, like a resize, draws the screen again.
+            let key = match event::read()? {
+                TermEvent::Key(key) => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char('c') => {
+                    self.context = !self.context;
+                    self.scroll = 0;
+                }
+                KeyCode::Char(' ') | KeyCode::Enter => {
+                    self.reveal = true;
+                    self.context = false;
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => self.scroll += 1,
+                KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+                KeyCode::PageDown => self.scroll += 10,
+                KeyCode::Char(c) if self.reveal => {
+                    let grade = c
+                        .to_digit(10)
+                        .and_then(|number| Grade::ALL.get((number as usize).wrapping_sub(1)));
+                    if let Some(grade) = grade {
+                        self.session.update_and_next(*grade, scheduler, rng);
+                        self.reveal = false;
+                        self.context = false;
+                        self.scroll = 0;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reviews at most `num_cards` due cards in the terminal and saves them.
+/// Returns the number of cards that were answered.
+pub fn run(db: &mut Database, num_cards: i32, scheduler: &dyn Scheduler) -> io::Result<usize> {
+    let mut session = Session::new();
+    session.fetch(db, num_cards, &[]);
+    if session.len() == 0 {
+        return Ok(0);
+    }
+    let mut review = Review {
+        session,
+        reveal: false,
+        context: false,
+        scroll: 0,
+        documents: HashMap::new(),
+    };
+    let mut rng = thread_rng();
+
+    // The answers given so far are saved even when the terminal fails, after
+    // the screen is left.
+    let result = Screen::enter().and_then(|_screen| review.answer(db, scheduler, &mut rng));
+    let reviewed = review.session.reviewed_cards().len();
+    review.session.save_to_database(db);
+    result.map(|_| reviewed)
+}
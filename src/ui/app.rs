@@ -1,5 +1,19 @@
 use super::{cards_ui::CardsUI, content_ui::DocumentUI, files_ui::FileUI, settings_ui::SettingsUI};
-use crate::database::Database;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use crate::{
+    database::{Database, MigrationError},
+    drill, import,
+    optimizer::{self, Report},
+    profile::{self, Location, DEFAULT_PROFILE},
+    sr::{Algorithm, Grade},
+    watcher::{Watched, Watcher},
+};
 use eframe::{egui, epi};
 use egui::{
     Button, CentralPanel, FontData, FontDefinitions, FontFamily, Rect, SidePanel, TopBottomPanel,
@@ -9,6 +23,9 @@ use rand::prelude::*;
 
 pub struct App {
     db: Database,
+    location: Location,
+    /// The settings of the profiles that are not open.
+    other_settings: HashMap<String, SettingsUI>,
     rng: ThreadRng,
     files: FileUI,
     document: DocumentUI,
@@ -16,12 +33,20 @@ pub struct App {
     cards: CardsUI,
     start_session: bool,
     fonts: FontDefinitions,
+    /// Runs while watching the imported files is enabled.
+    watcher: Option<Watcher>,
+    /// Receives the report of the optimizer, which runs on its own thread.
+    optimizer: Option<Receiver<Option<Report>>>,
 }
 
-impl Default for App {
-    fn default() -> Self {
-        let db = Database::connect().unwrap();
-        App {
+impl App {
+    /// Opens the database of the location, which fails when it can't be
+    /// migrated to the current schema.
+    pub fn new(location: Location) -> Result<Self, MigrationError> {
+        let db = Database::connect(&location.database)?;
+        Ok(App {
+            location,
+            other_settings: HashMap::new(),
             start_session: false,
             rng: thread_rng(),
             files: FileUI::new(&db),
@@ -29,8 +54,10 @@ impl Default for App {
             settings: SettingsUI::default(),
             cards: CardsUI::new(),
             fonts: FontDefinitions::default(),
+            watcher: None,
+            optimizer: None,
             db,
-        }
+        })
     }
 }
 
@@ -47,16 +74,64 @@ impl epi::App for App {
     ) {
         #[cfg(feature = "persistence")]
         if let Some(storage) = storage {
-            let settings: SettingsUI = epi::get_value(storage, epi::APP_KEY).unwrap_or_default();
-            self.settings = settings;
+            for name in self.profile_names() {
+                let mut settings: SettingsUI =
+                    epi::get_value(storage, &App::settings_key(&name)).unwrap_or_default();
+                settings.migrate();
+                if name == self.location.profile {
+                    self.settings = settings;
+                } else {
+                    self.other_settings.insert(name, settings);
+                }
+            }
         }
+        self.settings
+            .set_profiles(&self.location, self.profile_names());
+        self.settings.set_backups(self.db.backups());
+        self.store_settings();
     }
 
     fn update(&mut self, ctx: &egui::Context, frame: &epi::Frame) {
+        if let Some(name) = self.settings.switch_profile.take() {
+            self.switch_profile(&name);
+        }
+        if let Some(path) = self.settings.restore_backup.take() {
+            self.restore_backup(&path);
+        }
+        if self.settings.stored_changed {
+            self.store_settings();
+        }
         if self.cards.is_done() {
             self.cards.save_to_database(&mut self.db);
+            if self.settings.write_schedule {
+                match drill::write_back(&self.db, &self.cards.reviewed_cards()) {
+                    Ok(count) => println!("Wrote the schedule into {} files", count),
+                    Err(err) => println!("Error: {}", err),
+                }
+            }
+            self.back_up();
+            self.files.reload(&self.db);
             self.cards.reset();
         }
+        if self.settings.algorithm_changed {
+            if self.settings.algorithm == Algorithm::Fsrs {
+                match self.db.seed_fsrs_state() {
+                    Ok(count) => println!("Seeded FSRS state for {} cards", count),
+                    Err(err) => println!("Error: {:?}", err),
+                }
+                if !self.start_session {
+                    self.fetch_cards();
+                }
+            }
+            self.settings.algorithm_changed = false;
+        }
+        if self.settings.should_optimize {
+            self.start_optimizer(frame);
+            self.settings.should_optimize = false;
+        }
+        self.receive_report();
+        self.settings.optimizing = self.optimizer.is_some();
+        self.sync_changed_files(frame);
         TopBottomPanel::top("").min_height(0.0).show(ctx, |_ui| {});
         let x = 0.4 * ctx.used_size().x;
         SidePanel::right("Menu")
@@ -64,12 +139,24 @@ impl epi::App for App {
             .min_width(x)
             .show(ctx, |ui| {
                 if !self.start_session || self.cards.is_done() {
-                    self.files.update_files(ui, &mut self.db);
+                    self.files.update_files(ui, &mut self.db, &self.settings);
                     if self.files.should_import {
                         // TODO: This can probably lead to some hard cases.
                         // This should be handled later on.
-                        self.cards.fetch(&self.db, self.settings.num_cards);
+                        self.fetch_cards();
                         self.files.should_import = false;
+                        self.settings.set_backups(self.db.backups());
+                        if let Some(folder) = self.files.imported_folder.take() {
+                            if !self.settings.folders.contains(&folder) {
+                                self.settings.folders.push(folder);
+                            }
+                        }
+                        // The folder might be new, so the watcher is started again.
+                        self.watcher = None;
+                    }
+                    if self.files.decks_changed {
+                        self.fetch_cards();
+                        self.files.decks_changed = false;
                     }
                 } else {
                     egui::ScrollArea::vertical()
@@ -116,7 +203,14 @@ impl epi::App for App {
 
     #[cfg(feature = "persistence")]
     fn save(&mut self, storage: &mut dyn epi::Storage) {
-        epi::set_value(storage, epi::APP_KEY, &self.settings);
+        epi::set_value(
+            storage,
+            &App::settings_key(&self.location.profile),
+            &self.settings,
+        );
+        for (name, settings) in &self.other_settings {
+            epi::set_value(storage, &App::settings_key(name), settings);
+        }
     }
 }
 
@@ -135,48 +229,241 @@ impl App {
             .unwrap()
             .insert(0, "garamond_normal".to_owned());
 
-        self.cards.fetch(&self.db, self.settings.num_cards);
+        self.fetch_cards();
     }
 
-    fn lower_buttons(&mut self, ui: &mut Ui) {
+    /// Fetches the due cards of the decks that are selected in the files
+    /// panel, or of every deck.
+    fn fetch_cards(&mut self) {
+        let decks = self.files.selected_decks();
+        self.cards.fetch(&self.db, self.settings.num_cards, &decks);
+    }
+
+    /// The settings of the default profile keep the key of the older
+    /// versions, which had a single profile.
+    fn settings_key(profile: &str) -> String {
+        if profile == DEFAULT_PROFILE {
+            epi::APP_KEY.to_owned()
+        } else {
+            format!("settings-{}", profile)
+        }
+    }
+
+    fn profile_names(&self) -> Vec<String> {
+        let mut names = profile::profiles();
+        for name in self.other_settings.keys().chain([&self.location.profile]) {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// Opens the database and the settings of another profile. The profiles
+    /// are only switched outside of a session.
+    fn switch_profile(&mut self, name: &str) {
+        if name == self.location.profile || self.location.explicit {
+            return;
+        }
+        let location = Location::of_profile(name);
+        let db = match Database::connect(&location.database) {
+            Ok(db) => db,
+            Err(err) => {
+                println!("Error: {}", err);
+                return;
+            }
+        };
+        let settings = self.other_settings.remove(name).unwrap_or_default();
+        let previous = std::mem::replace(&mut self.settings, settings);
+        self.other_settings
+            .insert(self.location.profile.clone(), previous);
+        self.db = db;
+        self.location = location;
+        self.reload();
+    }
+
+    /// Fits the weights to the reviews on a worker thread, as it takes a
+    /// while for a large collection. The reviews are read here, since the
+    /// database connection can't leave the main thread.
+    fn start_optimizer(&mut self, frame: &epi::Frame) {
+        if self.optimizer.is_some() {
+            return;
+        }
+        let reviews = match self.db.load_reviews() {
+            Ok(reviews) => reviews,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return;
+            }
+        };
+        let weights = self.settings.fsrs_weights;
+        let (sender, receiver) = mpsc::channel();
+        let frame = frame.clone();
+        thread::spawn(move || {
+            let _ = sender.send(optimizer::optimize(&reviews, &weights));
+            frame.request_repaint();
+        });
+        self.optimizer = Some(receiver);
+    }
+
+    /// Shows the report once the optimizer is done.
+    fn receive_report(&mut self) {
+        let report = match &self.optimizer {
+            Some(receiver) => receiver.try_recv(),
+            None => return,
+        };
+        match report {
+            Ok(Some(report)) => self.settings.report = Some(report),
+            Ok(None) => println!("Not enough reviews to optimize the weights."),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => println!("Error: the optimizer stopped"),
+        }
+        self.optimizer = None;
+    }
+
+    /// Writes the settings that the command line and the server read into the
+    /// database of the profile.
+    fn store_settings(&mut self) {
+        let stored = self
+            .db
+            .set_backup_count(self.settings.backups)
+            .and_then(|_| self.db.set_algorithm(self.settings.algorithm))
+            .and_then(|_| self.db.set_fsrs_weights(&self.settings.fsrs_weights));
+        if let Err(err) = stored {
+            println!("Error: cannot store the settings: {}", err);
+        }
+        self.settings.stored_changed = false;
+    }
+
+    /// Snapshots the database at the end of a review session, and before the
+    /// saved files are synced.
+    fn back_up(&mut self) {
+        match self.db.back_up(self.settings.backups) {
+            Ok(Some(path)) => println!("Backed up the database to {}", path.display()),
+            Ok(None) => (),
+            Err(err) => println!("Error: cannot back up the database: {}", err),
+        }
+        self.settings.set_backups(self.db.backups());
+    }
+
+    /// Replaces the database with a snapshot. The state before it is backed
+    /// up, so it shows up in the list of backups.
+    fn restore_backup(&mut self, path: &Path) {
+        match self.db.restore(path, self.settings.backups) {
+            Ok(()) => println!("Restored {}", path.display()),
+            Err(err) => println!("{}: {}", path.display(), err),
+        }
+        self.reload();
+    }
+
+    /// Starts over with the cards and files of the open database.
+    fn reload(&mut self) {
+        self.files = FileUI::new(&self.db);
+        self.document = DocumentUI::new();
+        self.cards = CardsUI::new();
+        self.start_session = false;
+        self.watcher = None;
+        // The report belongs to the reviews of the database before.
+        self.optimizer = None;
+        self.fetch_cards();
+        self.settings
+            .set_profiles(&self.location, self.profile_names());
+        self.settings.set_backups(self.db.backups());
+        self.store_settings();
+    }
+
+    /// Syncs the files that were saved since the last frame, and starts or
+    /// stops the watcher when the setting changed.
+    fn sync_changed_files(&mut self, frame: &epi::Frame) {
+        if self.settings.watch_files != self.watcher.is_some() {
+            self.watcher = None;
+            if self.settings.watch_files {
+                let files = self.db.load_file_names().unwrap_or_default();
+                let watched = Watched {
+                    include: self.settings.include.clone(),
+                    exclude: self.settings.exclude.clone(),
+                    follow_links: self.settings.follow_links,
+                    ..Watched::new(
+                        &self.settings.folders,
+                        files.iter().map(|file| file.get_path()),
+                    )
+                };
+                let frame = frame.clone();
+                self.watcher = Some(Watcher::spawn(watched, move || frame.request_repaint()));
+            }
+        }
+        let changed = match &self.watcher {
+            Some(watcher) => watcher.changed_files(),
+            None => return,
+        };
+        if changed.is_empty() {
+            return;
+        }
+
+        // Like before an import, so the sync can be undone.
+        self.back_up();
+        for path in changed {
+            match import::sync_file(&self.db, &path, self.settings.write_ids) {
+                Ok(Some(summary)) => println!("{}: {}", path.display(), summary),
+                Ok(None) => (),
+                Err(err) => println!("{}: {}", path.display(), err),
+            }
+        }
+        self.document.clear_cache();
+        self.files.reload(&self.db);
+        // A running session keeps its cards.
+        if !self.start_session || self.cards.is_done() {
+            self.fetch_cards();
+        }
+    }
+
+    fn button_rect(ui: &Ui, x: f32, width: f32) -> Rect {
         let mut widget_size = ui.max_rect().size();
-        widget_size.x *= 0.20;
+        widget_size.x *= width;
         widget_size.y *= 0.40;
 
         let mut offset = ui.min_rect().size();
-        offset.x *= 0.10;
+        offset.x *= x;
         offset.y *= 0.30;
-        let widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
-        if !self.cards.is_repeat() {
+        Rect::from_min_size(ui.min_rect().min + offset, widget_size)
+    }
+
+    fn lower_buttons(&mut self, ui: &mut Ui) {
+        if !self.cards.is_reveal() && !self.cards.is_repeat() {
+            let widget_rect = App::button_rect(ui, 0.10, 0.20);
             if ui.put(widget_rect, Button::new("Repeat")).clicked() {
                 self.cards.repeat();
             }
-        } else {
-            if ui.put(widget_rect, Button::new("Question")).clicked() {
-                self.cards.unset_repeat();
+
+            let widget_rect = App::button_rect(ui, 0.70, 0.20);
+            if ui.put(widget_rect, Button::new("Reveal")).clicked() {
+                self.cards.reveal();
             }
+            return;
         }
 
-        let mut offset = ui.min_rect().size();
-        offset.x *= 0.40;
-        offset.y *= 0.30;
-        let widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
-        if ui.put(widget_rect, Button::new("Good")).clicked() {
-            // Update stats and grades.
-            self.cards.update_and_next(&mut self.rng);
+        // Once the answer is shown, the card is graded with one of the answers.
+        for (idx, grade) in Grade::ALL.iter().enumerate() {
+            let widget_rect = App::button_rect(ui, 0.02 + 0.20 * idx as f32, 0.18);
+            if ui.put(widget_rect, Button::new(grade.label())).clicked() {
+                // Update stats and grades.
+                let scheduler = self
+                    .settings
+                    .algorithm
+                    .scheduler(&self.settings.fsrs_weights);
+                self.cards
+                    .update_and_next(*grade, scheduler.as_ref(), &mut self.rng);
+            }
         }
 
-        let mut offset = ui.min_rect().size();
-        offset.x *= 0.70;
-        offset.y *= 0.30;
-        let widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
-        if !self.cards.is_reveal() && !self.cards.is_repeat() {
-            if ui.put(widget_rect, Button::new("Reveal")).clicked() {
-                self.cards.reveal();
+        let widget_rect = App::button_rect(ui, 0.82, 0.16);
+        if !self.cards.is_repeat() {
+            if ui.put(widget_rect, Button::new("Repeat")).clicked() {
+                self.cards.repeat();
             }
         } else {
-            if ui.put(widget_rect, Button::new("Next")).clicked() {
-                self.cards.next(&mut self.rng);
+            if ui.put(widget_rect, Button::new("Question")).clicked() {
+                self.cards.unset_repeat();
             }
         }
     }
@@ -4,56 +4,50 @@ use eframe::egui::{text::LayoutJob, Label, Rect, Ui};
 use egui::Context;
 use rand::prelude::*;
 
-use crate::{database::Database, org::FlashCard, ui::content_ui::StyleVisual};
+use crate::{
+    database::{Database, DeckKey},
+    org::FlashCard,
+    session::Session,
+    sr::{Grade, Scheduler},
+    ui::content_ui::StyleVisual,
+};
 
 use super::content_ui::DocumentUI;
 
 pub struct CardsUI {
-    cards: Vec<FlashCard>,
-    active_card: usize,
+    session: Session,
     repeat: bool,
     reveal: bool,
-    done: bool,
-    stats: HashMap<usize, bool>,
-    grades: HashMap<usize, i8>,
+    answers: HashMap<usize, LayoutJob>,
 }
 
 impl CardsUI {
     pub fn new() -> Self {
         Self {
-            cards: vec![],
-            active_card: 0,
+            session: Session::new(),
             repeat: false,
             reveal: false,
-            done: false,
-            stats: HashMap::new(),
-            grades: HashMap::new(),
+            answers: HashMap::new(),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.cards.len()
+        self.session.len()
     }
 
     pub fn is_done(&self) -> bool {
-        self.done
+        self.session.is_done()
     }
 
     pub fn show_content(&mut self, db: &Database, document: &mut DocumentUI, ui: &mut Ui, ctx: &Context) {
         if self.reveal || self.repeat {
-            document.load_item(db, self.cards[self.active_card].get_doc_id(), ui, ctx);
+            document.load_item(db, self.session.card().get_doc_id(), ui, ctx);
         }
     }
 
-    pub fn fetch(&mut self, db: &Database, num_cards: i32) {
-        // Select 20 flash cards from the
-        if let Ok(result) = db.get_flashcards(num_cards) {
-            self.cards = result;
-        }
-        for id in 0..self.cards.len() {
-            self.stats.entry(id).or_insert(false);
-            self.grades.entry(id).or_insert(4);
-        }
+    pub fn fetch(&mut self, db: &Database, num_cards: i32, decks: &[DeckKey]) {
+        self.session.fetch(db, num_cards, decks);
+        self.answers.clear();
     }
 
     pub fn show(&mut self, ui: &mut Ui, ctx: &Context) {
@@ -61,15 +55,14 @@ impl CardsUI {
         let widget_offset = 0.1 * ui.min_rect().size();
         let widget_rect = Rect::from_min_size(ui.min_rect().min + widget_offset, widget_size);
 
-        let card = &self.cards[self.active_card];
+        let card = self.session.card();
         if self.reveal || self.repeat {
-            let mut job = LayoutJob::default();
-            job.append(
-                card.get_answers(),
-                0.0,
-                StyleVisual::heading(ctx),
-            );
-            let label = Label::new(job);
+            // Answers are org source, so they are laid out like the content.
+            let job = self
+                .answers
+                .entry(self.session.active())
+                .or_insert_with(|| DocumentUI::layout_org(card.get_answers(), ctx));
+            let label = Label::new(job.clone());
             ui.put(widget_rect, label);
             return;
         }
@@ -83,65 +76,28 @@ impl CardsUI {
         ui.put(widget_rect, label);
     }
 
-    pub fn next(&mut self, rng: &mut ThreadRng) {
-        self.reveal = false;
-        self.repeat = false;
-        let vec: Vec<usize> = self
-            .stats
-            .iter()
-            .filter(|(_, y)| !*y)
-            .map(|(x, _)| *x)
-            .collect();
-
-        if vec.len() <= 0 {
-            self.done = true;
-            return;
-        }
-
-        let dist = rand::distributions::Uniform::new_inclusive(0, vec.len() - 1);
-        self.active_card = vec[rng.sample(dist)];
-    }
-
     pub fn reset(&mut self) {
-        if self.done {
-            for (_, value) in self.stats.iter_mut() {
-                *value = false;
-            }
-            self.cards = vec![];
-            self.done = false;
-            self.active_card = 0;
+        if self.session.is_done() {
+            self.answers.clear();
             self.reveal = false;
             self.repeat = false;
         }
+        self.session.reset();
     }
 
-    pub fn update_and_next(&mut self, rng: &mut ThreadRng) {
-        if let Some(grade) = self.grades.get(&self.active_card) {
-            if grade >= &2 || grade <= &-4 {
-                self.stats.entry(self.active_card).and_modify(|x| *x = true);
-                let stats = &mut self.cards[self.active_card].get_stats_mut();
-                stats.repeat(*grade);
-            }
-        }
-        self.grades
-            .entry(self.active_card)
-            .and_modify(|x| *x = *x + 1);
-        self.next(rng);
+    pub fn update_and_next(&mut self, grade: Grade, scheduler: &dyn Scheduler, rng: &mut ThreadRng) {
+        self.reveal = false;
+        self.repeat = false;
+        self.session.update_and_next(grade, scheduler, rng);
     }
 
     pub fn reveal(&mut self) {
         self.reveal = true;
-        self.grades
-            .entry(self.active_card)
-            .and_modify(|x| *x = *x - 1);
     }
 
     pub fn repeat(&mut self) {
         self.reveal = false;
         self.repeat = true;
-        self.grades
-            .entry(self.active_card)
-            .and_modify(|x| *x = *x - 1);
     }
 
     pub fn is_reveal(&self) -> bool {
@@ -156,7 +112,12 @@ impl CardsUI {
         self.repeat = false;
     }
 
-    pub fn save_to_database(&self, db: &mut Database) {
-        db.update_flashcards(&self.cards);
+    pub fn save_to_database(&mut self, db: &mut Database) {
+        self.session.save_to_database(db);
+    }
+
+    /// The cards that were answered in this session.
+    pub fn reviewed_cards(&self) -> Vec<&FlashCard> {
+        self.session.reviewed_cards()
     }
 }
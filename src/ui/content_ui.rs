@@ -35,6 +35,15 @@ impl StyleVisual {
         }
     }
 
+    fn code(ctx: &Context) -> TextFormat {
+        TextFormat {
+            font_id: TextStyle::Monospace.resolve(&ctx.style()),
+            color: ctx.style().visuals.text_color(),
+            background: ctx.style().visuals.code_bg_color,
+            ..Default::default()
+        }
+    }
+
     pub fn heading(ctx: &Context) -> TextFormat {
         TextFormat {
             // I need a way to get the current mode
@@ -107,7 +116,14 @@ impl DocumentUI {
             Element::List(list) => {
                 DocumentUI::handle_list(list, section_id, arena, &Style::Default, job, ctx)
             }
-            Element::SourceBlock(_source) => println!("TODO: Insert code block"),
+            Element::SourceBlock(source) => {
+                let mut data = source.contents.to_string();
+                if !data.ends_with('\n') {
+                    data += "\n";
+                }
+                job.append(&data, 0.0, StyleVisual::code(ctx));
+                job.append("\n", 0.0, TextFormat::default());
+            }
             _ => {}
         }
     }
@@ -276,6 +292,26 @@ impl DocumentUI {
         }
     }
 
+    /// Lays out a piece of org text, like the answer of a card.
+    pub fn layout_org(text: &str, ctx: &Context) -> LayoutJob {
+        let mut job = LayoutJob::default();
+        let content_data = Org::parse(text);
+        let arena = content_data.arena();
+        let document = content_data.document();
+        if let Some(section_id) = document.section_node() {
+            DocumentUI::handle_section(section_id, arena, &mut job, ctx);
+        }
+        for headline in document.children(&content_data) {
+            DocumentUI::handle_normal_headline(headline.headline_node(), arena, &1, &mut job, ctx);
+        }
+        job
+    }
+
+    /// Drops the cached contents, so they are loaded again from the database.
+    pub fn clear_cache(&mut self) {
+        self.cached_content.clear();
+    }
+
     /// If the function is called for the first time, it fetches the content
     /// from the database and caches.
     /// By default function displays the content in the main content section.
@@ -1,37 +1,64 @@
-use std::fs;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use eframe::egui;
-use egui::{Button, Rect, ScrollArea};
-use orgize::Org;
+use egui::{Button, Checkbox, ComboBox, Rect, ScrollArea};
 
-use crate::database::Database;
-use crate::files::File;
-use crate::org::Document;
-use walkdir::WalkDir;
+use crate::anki;
+use crate::csv::{self, Mapping};
+use crate::database::{Database, Deck, DeckKey};
+use crate::import;
+use super::settings_ui::SettingsUI;
+
+/// A CSV table waiting for its columns to be mapped.
+#[derive(Debug)]
+struct CsvImport {
+    path: PathBuf,
+    records: Vec<Vec<String>>,
+    mapping: Mapping,
+    /// The dry run of the mapping, computed again when the mapping changes.
+    preview: Option<(Mapping, String)>,
+}
 
 #[derive(Debug)]
 pub struct FileUI {
     picked_path: Option<String>,
-    loaded_files: Vec<File>,
+    /// The folder that was imported last, to be watched from now on.
+    pub imported_folder: Option<String>,
+    /// The decks of the imported files, with their counts.
+    decks: Vec<Deck>,
+    /// The decks to study. Without any, every deck is studied.
+    selected: HashSet<DeckKey>,
+    /// The selection changed, so the due cards are fetched again.
+    pub decks_changed: bool,
     imported: bool,
     pub should_import: bool,
+    csv: Option<CsvImport>,
+    /// The documents that can be exported on their own.
+    documents: Vec<(i64, String)>,
+    export_document: Option<i64>,
 }
 
 impl FileUI {
     pub fn new(db: &Database) -> FileUI {
-        let mut files: Vec<File> = vec![];
-        if let Ok(fetched_files) = db.load_file_names() {
-            files = fetched_files;
-        };
         FileUI {
             picked_path: None,
+            imported_folder: None,
             imported: false,
-            loaded_files: files,
+            decks: db.decks().unwrap_or_default(),
+            selected: HashSet::new(),
+            decks_changed: false,
             should_import: false,
+            csv: None,
+            documents: FileUI::load_documents(db),
+            export_document: None,
         }
     }
 
-    pub fn update_files(&mut self, ui: &mut egui::Ui, db: &mut Database) {
+    pub fn update_files(&mut self, ui: &mut egui::Ui, db: &mut Database, settings: &SettingsUI) {
         let mut widget_size = ui.max_rect().size();
         widget_size.x *= 0.96;
         widget_size.y *= 0.74;
@@ -42,24 +69,33 @@ impl FileUI {
 
         let mut widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
 
-        ui.allocate_ui_at_rect(widget_rect, |ui| {
-            egui::ScrollArea::vertical()
-                .max_width(widget_size.x)
-                .auto_shrink([false, false])
-                .show(ui, |ui| {
-                self.loaded_files.iter().for_each(|x| {
-                    ui.label(x.get_name());
+        if self.csv.is_some() {
+            ui.allocate_ui_at_rect(widget_rect, |ui| {
+                ScrollArea::vertical()
+                    .max_width(widget_size.x)
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| self.csv_ui(ui, db, settings));
+            });
+        } else {
+            ui.allocate_ui_at_rect(widget_rect, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_width(widget_size.x)
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                    for deck in &self.decks {
+                        self.decks_changed |= FileUI::deck_ui(ui, deck, &mut self.selected);
+                    }
                 });
             });
-        });
+        }
         ui.separator();
         widget_size = ui.max_rect().size();
-        widget_size.x *= 0.40;
-        widget_size.y *= 0.10;
+        widget_size.x *= 0.28;
+        widget_size.y *= 0.07;
 
         offset = ui.min_rect().size();
-        offset.x *= 0.30;
-        offset.y *= 0.80;
+        offset.x *= 0.04;
+        offset.y *= 0.79;
 
         widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
 
@@ -70,61 +106,328 @@ impl FileUI {
             self.imported = true;
             self.should_import = true;
         }
-        if self.imported {
-            self.import_and_fill(db);
-            if let Ok(fetched_files) = db.load_file_names() {
-                self.loaded_files = fetched_files;
+
+        offset = ui.min_rect().size();
+        offset.x *= 0.36;
+        offset.y *= 0.79;
+        widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
+
+        if ui.put(widget_rect, Button::new("Import from Anki")).clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Anki package", &["apkg", "colpkg"])
+                .pick_file()
+            {
+                if FileUI::back_up(db, settings) {
+                    match anki::import_package(db, &path) {
+                        Ok(summary) => println!("Imported {}", summary),
+                        Err(err) => println!("{}: {}", path.display(), err),
+                    }
+                }
+                self.reload(db);
+                self.should_import = true;
+            }
+        }
+
+        offset = ui.min_rect().size();
+        offset.x *= 0.68;
+        offset.y *= 0.79;
+        widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
+
+        if ui.put(widget_rect, Button::new("Export to Anki")).clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Anki package", &["apkg"])
+                .set_file_name("flashcards.apkg")
+                .save_file()
+            {
+                match anki::export(db, &path) {
+                    Ok(summary) => println!("Exported {}", summary),
+                    Err(err) => println!("{}: {}", path.display(), err),
+                }
+            }
+        }
+
+        offset = ui.min_rect().size();
+        offset.x *= 0.04;
+        offset.y *= 0.88;
+        widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
+
+        if ui.put(widget_rect, Button::new("Import CSV")).clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Table", &["csv", "tsv", "txt"])
+                .pick_file()
+            {
+                match fs::read_to_string(&path) {
+                    Ok(text) => {
+                        let records = csv::parse(&text, csv::delimiter(&path));
+                        let mapping =
+                            Mapping::guess(records.first().map(|r| r.as_slice()).unwrap_or(&[]));
+                        self.csv = Some(CsvImport {
+                            path,
+                            records,
+                            mapping,
+                            preview: None,
+                        });
+                    }
+                    Err(err) => println!("{}: {}", path.display(), err),
+                }
+            }
+        }
+
+        offset = ui.min_rect().size();
+        offset.x *= 0.36;
+        offset.y *= 0.88;
+        widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
+
+        let documents = &self.documents;
+        let export_document = &mut self.export_document;
+        ui.allocate_ui_at_rect(widget_rect, |ui| {
+            let selected = documents
+                .iter()
+                .find(|(id, _)| Some(*id) == *export_document)
+                .map(|(_, title)| title.as_str())
+                .unwrap_or("All documents");
+            ComboBox::from_id_source("export_document")
+                .selected_text(selected)
+                .width(widget_size.x)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(export_document, None, "All documents");
+                    for (id, title) in documents {
+                        ui.selectable_value(export_document, Some(*id), title);
+                    }
+                });
+        });
+
+        offset = ui.min_rect().size();
+        offset.x *= 0.68;
+        offset.y *= 0.88;
+        widget_rect = Rect::from_min_size(ui.min_rect().min + offset, widget_size);
+
+        if ui.put(widget_rect, Button::new("Export CSV")).clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("CSV", &["csv"])
+                .add_filter("TSV", &["tsv"])
+                .set_file_name("flashcards.csv")
+                .save_file()
+            {
+                match csv::export(db, self.export_document, &path) {
+                    Ok(count) => println!("Exported {} cards", count),
+                    Err(err) => println!("{}: {}", path.display(), err),
+                }
             }
+        }
+        if self.imported {
+            self.import_and_fill(db, settings);
+            self.reload(db);
             self.imported = false;
         }
     }
 
-    fn is_hidden(&self, entry: &walkdir::DirEntry) -> bool {
-        entry
-            .file_name()
-            .to_str()
-            .map(|s| s.starts_with("."))
-            .unwrap_or(false)
+    pub fn reload(&mut self, db: &Database) {
+        if let Ok(decks) = db.decks() {
+            self.decks = decks;
+        }
+        // The decks that are gone are not studied anymore.
+        let mut keys = HashSet::new();
+        let mut stack: Vec<&Deck> = self.decks.iter().collect();
+        while let Some(deck) = stack.pop() {
+            keys.insert(&deck.key);
+            stack.extend(deck.children.iter());
+        }
+        let before = self.selected.len();
+        self.selected.retain(|key| keys.contains(key));
+        self.decks_changed |= self.selected.len() != before;
+        self.documents = FileUI::load_documents(db);
     }
 
-    fn import_and_fill(&mut self, db: &mut Database) {
-        let picked_path = match &self.picked_path {
-            Some(it) => it,
-            _ => return, // TODO: Handle error here.
+    /// The decks to study, or none to study every deck.
+    pub fn selected_decks(&self) -> Vec<DeckKey> {
+        self.selected.iter().cloned().collect()
+    }
+
+    /// Shows the deck with its counts and a box to study it, and the decks
+    /// within it below it. Returns whether the box was toggled.
+    fn deck_ui(ui: &mut egui::Ui, deck: &Deck, selected: &mut HashSet<DeckKey>) -> bool {
+        let name = match &deck.key {
+            DeckKey::Tag(..) => format!(":{}:", deck.name),
+            _ => deck.name.clone(),
         };
-        // Iterate through this picked path and then add them to database
-        WalkDir::new(picked_path)
-            .max_depth(1)
-            .into_iter()
-            .filter_entry(|e| !self.is_hidden(e))
-            .filter_map(|file| file.ok())
-            .for_each(|entry| {
-                let name = entry.file_name();
-                if !name.to_string_lossy().ends_with(".org") {
-                    return;
-                }
-                db.insert_file(entry.path()).ok();
-                // Perform the main import here.
-                let contents = String::from_utf8(fs::read(&entry.path()).unwrap()).unwrap();
-                // Parse the contents.
-                let content_data = Org::parse(&contents);
-                let arena = content_data.arena();
-
-                let mut org = Document::new();
-                // Load the data.
-                for headline in content_data.headlines() {
-                    org.handle_context(&headline, arena)
+        let counts = &deck.counts;
+        let label = format!(
+            "{}   {} due, {} new, {} cards",
+            name, counts.due, counts.new, counts.total
+        );
+        let mut checked = selected.contains(&deck.key);
+        let mut changed = false;
+        if ui.add(Checkbox::new(&mut checked, label)).changed() {
+            if checked {
+                selected.insert(deck.key.clone());
+            } else {
+                selected.remove(&deck.key);
+            }
+            changed = true;
+        }
+        if !deck.children.is_empty() {
+            ui.indent(&deck.key, |ui| {
+                for child in &deck.children {
+                    changed |= FileUI::deck_ui(ui, child, selected);
                 }
-                match db.insert_documents(&org.get_contents(), &org.get_title()) {
-                    Ok(id) => {
-                        org.update_id(id);
+            });
+        }
+        changed
+    }
+
+    fn load_documents(db: &Database) -> Vec<(i64, String)> {
+        db.load_documents()
+            .map(|documents| {
+                documents
+                    .iter()
+                    .map(|document| (document.get_id(), document.get_title().to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The column mapping of a CSV import, with a preview of what the import
+    /// would change.
+    fn csv_ui(&mut self, ui: &mut egui::Ui, db: &mut Database, settings: &SettingsUI) {
+        let table = match &mut self.csv {
+            Some(table) => table,
+            None => return,
+        };
+        let title = table
+            .path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        ui.heading(&title);
+        ui.add(Checkbox::new(
+            &mut table.mapping.header,
+            "First row holds the column names",
+        ));
+
+        let width = table.records.iter().map(|r| r.len()).max().unwrap_or(0);
+        let names: Vec<String> = (0..width)
+            .map(|column| match table.records.first() {
+                Some(first) if table.mapping.header => first
+                    .get(column)
+                    .cloned()
+                    .unwrap_or_else(|| format!("Column {}", column + 1)),
+                _ => format!("Column {}", column + 1),
+            })
+            .collect();
+        let mapping = &mut table.mapping;
+        for (label, column) in [("Question", &mut mapping.question), ("Answer", &mut mapping.answer)] {
+            let selected = names.get(*column).cloned().unwrap_or_default();
+            ComboBox::from_label(label)
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (idx, name) in names.iter().enumerate() {
+                        ui.selectable_value(column, idx, name);
                     }
-                    Err(_) => {
-                        org.update_id(db.get_last_id());
+                });
+        }
+        for (label, column) in [("Context", &mut mapping.context), ("Tags", &mut mapping.tags)] {
+            let selected = column
+                .and_then(|column| names.get(column).cloned())
+                .unwrap_or_else(|| String::from("None"));
+            ComboBox::from_label(label)
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(column, None, "None");
+                    for (idx, name) in names.iter().enumerate() {
+                        ui.selectable_value(column, Some(idx), name);
                     }
+                });
+        }
+
+        // The dry run reads the database, so it only runs for a new mapping.
+        if table.preview.as_ref().map(|(m, _)| m) != Some(&table.mapping) {
+            // A table that was never imported has no cards to update.
+            let file_id = db.find_file(&table.path).ok().flatten().unwrap_or(0);
+            let summary =
+                csv::import(db, &table.records, &table.mapping, &title, file_id, true);
+            let preview = match summary {
+                Ok(summary) => summary.to_string(),
+                Err(err) => err.to_string(),
+            };
+            table.preview = Some((table.mapping.clone(), preview));
+        }
+        if let Some((_, preview)) = &table.preview {
+            ui.label(preview);
+        }
+        for row in csv::rows(&table.records, &table.mapping, &title)
+            .iter()
+            .flatten()
+            .take(5)
+        {
+            ui.label(format!("{}: {} → {}", row.context, row.question, row.answer));
+        }
+
+        let mut close = false;
+        ui.horizontal(|ui| {
+            if ui.button("Import").clicked() && FileUI::back_up(db, settings) {
+                let result = db.insert_file(&table.path).map_err(|err| err.into()).and_then(|file_id| {
+                    csv::import(db, &table.records, &table.mapping, &title, file_id, false)
+                });
+                match result {
+                    Ok(summary) => println!("{}: {}", table.path.display(), summary),
+                    Err(err) => println!("{}: {}", table.path.display(), err),
                 }
-                db.insert_flashcards(org.get_cards()).ok();
-            });
+                self.should_import = true;
+                close = true;
+            }
+            if ui.button("Cancel").clicked() {
+                close = true;
+            }
+        });
+        if close {
+            self.csv = None;
+            self.reload(db);
+        }
+    }
+
+    fn import_and_fill(&mut self, db: &mut Database, settings: &SettingsUI) {
+        let picked_path = match &self.picked_path {
+            Some(it) => Path::new(it),
+            _ => return, // TODO: Handle error here.
+        };
+        if !FileUI::back_up(db, settings) {
+            return;
+        }
+        let mut unchanged = 0;
+        // Iterate through this picked path and then add them to database
+        let paths = import::find_files(
+            picked_path,
+            &settings.include,
+            &settings.exclude,
+            settings.follow_links,
+        );
+        for path in paths {
+            let name = path.strip_prefix(picked_path).unwrap_or(&path).display();
+            match import::sync_file(db, &path, settings.write_ids) {
+                Ok(Some(summary)) => println!("{}: {}", name, summary),
+                Ok(None) => unchanged += 1,
+                Err(err) => println!("{}: {}", name, err),
+            }
+        }
+        if unchanged > 0 {
+            println!("{} unchanged files skipped", unchanged);
+        }
+        self.imported_folder = Some(picked_path.display().to_string());
         self.imported = true;
     }
+
+    /// Snapshots the database before an import. The import is skipped when
+    /// the snapshot fails, so it can always be undone.
+    fn back_up(db: &Database, settings: &SettingsUI) -> bool {
+        match db.back_up(settings.backups) {
+            Ok(Some(path)) => println!("Backed up the database to {}", path.display()),
+            Ok(None) => (),
+            Err(err) => {
+                println!("Error: cannot back up the database: {}", err);
+                return false;
+            }
+        }
+        true
+    }
 }
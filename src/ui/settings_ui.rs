@@ -1,6 +1,21 @@
-use eframe::egui::{Context, FontDefinitions, Slider, Style, TextStyle, Ui};
+use std::path::PathBuf;
+
+use eframe::egui::{
+    Button, Checkbox, ComboBox, Context, FontDefinitions, Slider, Style, TextStyle, Ui,
+};
 use egui::{Color32, FontFamily, FontId, Visuals};
 
+use crate::{
+    database::{self, Backup, BackupSummary, DEFAULT_BACKUPS},
+    optimizer::Report,
+    profile::{self, Location},
+    sr::{Algorithm, FSRS_WEIGHTS},
+};
+
+/// The version of the stored settings. It is raised when a default changes
+/// in a way that the settings stored before should follow.
+const SETTINGS_VERSION: u32 = 1;
+
 /// Contains different vari that allows to handle different settings.
 /// This maintains the exclusive state of the different variables that can be
 /// tuned.
@@ -9,29 +24,134 @@ use egui::{Color32, FontFamily, FontId, Visuals};
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serde", serde(default))]
 pub struct SettingsUI {
+    /// Settings stored before the versions read as version 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    version: u32,
     spacing: f32,
     body_size: f32,
     heading_size: f32,
     button_size: f32,
     pub style: Style,
     pub num_cards: i32,
+    pub algorithm: Algorithm,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub algorithm_changed: bool,
+    pub fsrs_weights: [f64; 17],
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub should_optimize: bool,
+    /// The optimizer is running, it can't be started again until it is done.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub optimizing: bool,
+    /// Result of the last optimizer run, waiting to be adopted.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub report: Option<Report>,
+    /// Gives the card headlines without an `:ID:` one while importing.
+    pub write_ids: bool,
+    /// Writes the schedule of the reviewed cards into their org files, in
+    /// the format of org-drill.
+    pub write_schedule: bool,
+    /// Comma separated globs of the files to import from a folder.
+    pub include: String,
+    /// Comma separated globs of the files and directories to leave out.
+    pub exclude: String,
+    pub follow_links: bool,
+    /// Syncs the imported files when they are saved.
+    pub watch_files: bool,
+    /// The imported folders. The watcher looks for new and saved files in
+    /// them with the include and exclude globs.
+    pub folders: Vec<String>,
+    /// The open profile. Every profile has its own settings.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    profile: String,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    profiles: Vec<String>,
+    /// The database path was given on the command line, so the profile
+    /// can't be switched.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    explicit_database: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    new_profile: String,
+    /// The profile to open instead, once another one is picked.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub switch_profile: Option<String>,
+    /// The number of snapshots of the database that are kept.
+    pub backups: usize,
+    /// The backups, the scheduler or the weights changed, which are kept in
+    /// the database for the command line as well.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stored_changed: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    backup_list: Vec<Backup>,
+    /// The snapshot picked to restore, shown with its counts until the
+    /// restore is confirmed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    preview: Option<(Backup, Result<BackupSummary, String>)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub restore_backup: Option<PathBuf>,
 }
 
 impl Default for SettingsUI {
     fn default() -> Self {
         Self {
+            version: SETTINGS_VERSION,
             spacing: 5.0,
             body_size: 22.0,
             heading_size: 26.0,
             button_size: 27.0,
             style: Style::default(),
             num_cards: 25,
+            algorithm: Algorithm::default(),
+            algorithm_changed: false,
+            fsrs_weights: FSRS_WEIGHTS,
+            should_optimize: false,
+            optimizing: false,
+            report: None,
+            write_ids: false,
+            write_schedule: false,
+            include: String::from("*.org, *.md"),
+            exclude: String::new(),
+            follow_links: false,
+            watch_files: false,
+            folders: vec![],
+            profile: String::new(),
+            profiles: vec![],
+            explicit_database: false,
+            new_profile: String::new(),
+            switch_profile: None,
+            backups: DEFAULT_BACKUPS,
+            stored_changed: false,
+            backup_list: vec![],
+            preview: None,
+            restore_backup: None,
         }
     }
 }
 
 impl SettingsUI {
+    /// Brings settings stored by an older version up to date. Markdown is
+    /// imported since version 1, so the old default of the include globs gets
+    /// it as well.
+    pub fn migrate(&mut self) {
+        if self.version < 1 && self.include.trim() == "*.org" {
+            self.include = String::from("*.org, *.md");
+        }
+        self.version = SETTINGS_VERSION;
+    }
+
+    pub fn set_profiles(&mut self, location: &Location, profiles: Vec<String>) {
+        self.profile = location.profile.clone();
+        self.profiles = profiles;
+        self.explicit_database = location.explicit;
+    }
+
+    pub fn set_backups(&mut self, backups: Vec<Backup>) {
+        self.backup_list = backups;
+        self.preview = None;
+    }
+
     pub fn ui(&mut self, ctx: &Context, ui: &mut Ui, fonts: &mut FontDefinitions) {
+        self.profile_ui(ui);
+
         ui.horizontal(|ui| {
             ui.selectable_value(&mut self.style.visuals, Self::light(), "☀ Light");
             ui.selectable_value(&mut self.style.visuals, Visuals::dark(), "🌙 Dark");
@@ -57,9 +177,54 @@ impl SettingsUI {
             });
 
             ui.horizontal(|ui| {
-                ui.label("Number of cards");
+                ui.label("Max due cards");
                 ui.add(Slider::new(&mut self.num_cards, 10..=200));
             });
+
+            ui.add(Checkbox::new(
+                &mut self.write_ids,
+                "Write card IDs into org files",
+            ));
+            ui.add(Checkbox::new(
+                &mut self.write_schedule,
+                "Write scheduling into org files",
+            ));
+
+            ui.horizontal(|ui| {
+                ui.label("Import files");
+                ui.text_edit_singleline(&mut self.include);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Skip files");
+                ui.text_edit_singleline(&mut self.exclude);
+            });
+            ui.add(Checkbox::new(
+                &mut self.follow_links,
+                "Follow symbolic links",
+            ));
+            ui.add(Checkbox::new(
+                &mut self.watch_files,
+                "Sync files when they are saved",
+            ));
+
+            ui.horizontal(|ui| {
+                ui.label("Scheduler");
+                for algorithm in [Algorithm::Sm2, Algorithm::Fsrs] {
+                    if ui
+                        .selectable_value(&mut self.algorithm, algorithm, algorithm.label())
+                        .changed()
+                    {
+                        self.algorithm_changed = true;
+                        self.stored_changed = true;
+                    }
+                }
+            });
+
+            if self.algorithm == Algorithm::Fsrs {
+                self.optimizer_ui(ui);
+            }
+
+            self.backup_ui(ui);
         });
 
         self.set_style();
@@ -67,6 +232,139 @@ impl SettingsUI {
         ctx.set_fonts(fonts.clone());
     }
 
+    fn profile_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Profile");
+            if self.explicit_database {
+                ui.label(format!(
+                    "{}, the database is set by --database or FLASHCARDS_DB",
+                    self.profile
+                ));
+                return;
+            }
+            let mut selected = self.profile.clone();
+            ComboBox::from_id_source("profile")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in &self.profiles {
+                        ui.selectable_value(&mut selected, name.clone(), name);
+                    }
+                });
+            if selected != self.profile {
+                self.switch_profile = Some(selected);
+            }
+
+            ui.text_edit_singleline(&mut self.new_profile);
+            let name = self.new_profile.trim();
+            let button = ui.add_enabled(profile::is_valid_name(name), Button::new("New profile"));
+            if button.clicked() {
+                self.switch_profile = Some(name.to_owned());
+                self.new_profile.clear();
+            }
+        });
+    }
+
+    fn backup_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Backups kept");
+            if ui.add(Slider::new(&mut self.backups, 0..=50)).changed() {
+                self.stored_changed = true;
+            }
+        });
+
+        if let Some((backup, summary)) = &self.preview {
+            ui.label(format!("Restore {}?", backup.name()));
+            match summary {
+                Ok(summary) => {
+                    let stats = &summary.stats;
+                    ui.label(format!(
+                        "{} cards, {} new, {} due, {} documents",
+                        stats.cards, stats.new_cards, stats.due, stats.documents
+                    ));
+                    let last_review = summary
+                        .last_review
+                        .map(|date| date.format("%Y-%m-%d").to_string())
+                        .unwrap_or_else(|| String::from("never"));
+                    ui.label(format!(
+                        "{} reviews, the last one {}",
+                        stats.reviews, last_review
+                    ));
+                }
+                Err(err) => {
+                    ui.label(format!("Error: {}", err));
+                }
+            }
+            let mut close = false;
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(summary.is_ok(), Button::new("Restore"))
+                    .clicked()
+                {
+                    self.restore_backup = Some(backup.path.clone());
+                    close = true;
+                }
+                close |= ui.button("Cancel").clicked();
+            });
+            if close {
+                self.preview = None;
+            }
+            return;
+        }
+
+        ui.collapsing(format!("Backups ({})", self.backup_list.len()), |ui| {
+            for backup in &self.backup_list {
+                ui.horizontal(|ui| {
+                    ui.label(backup.taken_at.format("%Y-%m-%d %H:%M:%S").to_string());
+                    if ui.button("Restore…").clicked() {
+                        let summary =
+                            database::inspect_backup(&backup.path).map_err(|err| err.to_string());
+                        self.preview = Some((backup.clone(), summary));
+                    }
+                });
+            }
+        });
+    }
+
+    fn optimizer_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if self.optimizing {
+                ui.label("Optimizing…");
+            } else if ui.button("Optimize").clicked() {
+                self.should_optimize = true;
+            }
+            if ui.button("Default weights").clicked() {
+                self.fsrs_weights = FSRS_WEIGHTS;
+                self.report = None;
+                self.stored_changed = true;
+            }
+        });
+
+        let mut adopt = false;
+        if let Some(report) = &self.report {
+            ui.label(format!("Fitted on {} reviews", report.predictions));
+            ui.label(format!(
+                "Log-loss: {:.4} → {:.4}",
+                report.before.log_loss, report.after.log_loss
+            ));
+            ui.label(format!(
+                "RMSE: {:.4} → {:.4}",
+                report.before.rmse, report.after.rmse
+            ));
+            ui.horizontal(|ui| {
+                adopt = ui.button("Adopt").clicked();
+                if ui.button("Discard").clicked() {
+                    self.report = None;
+                }
+            });
+        }
+        if adopt {
+            if let Some(report) = self.report.take() {
+                self.fsrs_weights = report.weights;
+                self.stored_changed = true;
+            }
+        }
+    }
+
     fn set_style(&mut self) {
         self.style.spacing.item_spacing.y = self.spacing;
         self.style.text_styles.insert(
@@ -0,0 +1,274 @@
+//! Watches the imported folders and files, so notes that are edited while the
+//! app is open are synced right away.
+//!
+//! The files are polled from a background thread, which only compares
+//! modification times. A folder is walked once, after that only the
+//! directories whose modification time changed are listed again, as a file
+//! that is added, removed or renamed changes the time of its directory. The
+//! files are synced by the receiver, since the database connection can't
+//! leave the main thread.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::import;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Watcher {
+    changes: Receiver<PathBuf>,
+    stop: Arc<AtomicBool>,
+}
+
+/// The files a watcher looks at.
+pub struct Watched {
+    /// The imported folders, walked like the import walks them.
+    pub folders: Vec<PathBuf>,
+    /// The imported files that are not in any of the folders.
+    pub files: Vec<PathBuf>,
+    /// Comma separated globs, relative to the folder, like for the import.
+    pub include: String,
+    pub exclude: String,
+    pub follow_links: bool,
+}
+
+impl Watched {
+    /// Watches the folders, and the files that are outside of them.
+    pub fn new<'a, I>(folders: &[String], files: I) -> Watched
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let folders: Vec<PathBuf> = folders.iter().map(PathBuf::from).collect();
+        let files = files
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|file| import::is_watched(file))
+            .filter(|file| !folders.iter().any(|folder| file.starts_with(folder)))
+            .collect();
+        Watched {
+            folders,
+            files,
+            include: String::new(),
+            exclude: String::new(),
+            follow_links: false,
+        }
+    }
+}
+
+impl Watcher {
+    /// Starts watching the files. `notify` is called after changes are
+    /// sent, so the receiver can wake up.
+    pub fn spawn<F>(watched: Watched, notify: F) -> Watcher
+    where
+        F: Fn() + Send + 'static,
+    {
+        let (sender, changes) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stopped = stop.clone();
+        thread::spawn(move || {
+            let mut poller = Poller::new(watched);
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(POLL_INTERVAL);
+                let mut sent = false;
+                for path in poller.poll() {
+                    sent |= sender.send(path).is_ok();
+                }
+                if sent {
+                    notify();
+                }
+            }
+        });
+        Watcher { changes, stop }
+    }
+
+    /// Returns the files that were saved since the last call.
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.changes.try_iter().collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// The modification times of the watched directories and files.
+struct Poller {
+    watched: Watched,
+    /// The directories of the folders, with the index of their folder.
+    dirs: HashMap<PathBuf, (usize, SystemTime)>,
+    files: HashMap<PathBuf, SystemTime>,
+}
+
+impl Poller {
+    fn new(watched: Watched) -> Self {
+        let mut poller = Poller {
+            watched,
+            dirs: HashMap::new(),
+            files: HashMap::new(),
+        };
+        for folder in 0..poller.watched.folders.len() {
+            let root = poller.watched.folders[folder].clone();
+            poller.list(folder, &root, true);
+        }
+        for file in &poller.watched.files {
+            if let Some(modified) = modified(file) {
+                poller.files.insert(file.clone(), modified);
+            }
+        }
+        poller
+    }
+
+    /// Lists the directory `dir` of a folder, and everything below it with
+    /// `recursive`. Returns the files that are new.
+    fn list(&mut self, folder: usize, dir: &Path, recursive: bool) -> Vec<PathBuf> {
+        let watched = &self.watched;
+        let (dirs, files) = import::list_folder(
+            &watched.folders[folder],
+            dir,
+            &watched.include,
+            &watched.exclude,
+            watched.follow_links,
+            recursive,
+        );
+        if let Some(modified) = modified(dir) {
+            self.dirs.insert(dir.to_path_buf(), (folder, modified));
+        }
+
+        let mut added = vec![];
+        for sub_dir in dirs {
+            if recursive {
+                if let Some(modified) = modified(&sub_dir) {
+                    self.dirs.insert(sub_dir, (folder, modified));
+                }
+            } else if !self.dirs.contains_key(&sub_dir) {
+                added.extend(self.list(folder, &sub_dir, true));
+            }
+        }
+        if !recursive {
+            // The files that were removed or renamed.
+            self.files
+                .retain(|path, _| path.parent() != Some(dir) || files.contains(path));
+        }
+        for file in files.into_iter().filter(|file| import::is_watched(file)) {
+            if self.files.contains_key(&file) {
+                continue;
+            }
+            if let Some(modified) = modified(&file) {
+                self.files.insert(file.clone(), modified);
+                added.push(file);
+            }
+        }
+        added
+    }
+
+    /// Returns the files that were added or saved since the last poll.
+    fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        let dirs: Vec<(PathBuf, usize, SystemTime)> = self
+            .dirs
+            .iter()
+            .map(|(dir, (folder, modified))| (dir.clone(), *folder, *modified))
+            .collect();
+        for (dir, folder, listed) in dirs {
+            match modified(&dir) {
+                Some(modified) if modified == listed => {}
+                Some(_) => changed.extend(self.list(folder, &dir, false)),
+                None => {
+                    self.dirs.retain(|path, _| !path.starts_with(&dir));
+                    self.files.retain(|path, _| !path.starts_with(&dir));
+                }
+            }
+        }
+
+        for (path, listed) in self.files.iter_mut() {
+            // A file that is missing now might be in the middle of a save.
+            match modified(path) {
+                Some(modified) if modified != *listed => {
+                    *listed = modified;
+                    if !changed.contains(path) {
+                        changed.push(path.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    fn touch(path: &Path, seconds: u64) {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn poll_added_and_saved_files() {
+        let root = std::env::temp_dir().join(format!("flashcards-watch-{}", std::process::id()));
+        fs::create_dir_all(root.join("daily")).unwrap();
+        touch(&root.join("notes.org"), 1);
+        touch(&root.join("daily/monday.org"), 1);
+        let mut poller = Poller::new(Watched {
+            include: "*.org".to_owned(),
+            exclude: "archive/**".to_owned(),
+            ..Watched::new(&[root.display().to_string()], [])
+        });
+        assert_eq!(poller.files.len(), 2);
+        assert!(poller.poll().is_empty());
+
+        touch(&root.join("notes.org"), 2);
+        assert_eq!(poller.poll(), [root.join("notes.org")]);
+
+        // New files are picked up if the import would read them.
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::create_dir_all(root.join("weekly")).unwrap();
+        touch(&root.join("archive/old.org"), 1);
+        touch(&root.join("weekly/first.org"), 1);
+        touch(&root.join("daily/tuesday.org"), 1);
+        touch(&root.join("daily/table.csv"), 1);
+        let mut added = poller.poll();
+        added.sort();
+        assert_eq!(
+            added,
+            [
+                root.join("daily/tuesday.org"),
+                root.join("weekly/first.org")
+            ]
+        );
+
+        fs::remove_dir_all(root.join("daily")).unwrap();
+        assert!(poller.poll().is_empty());
+        assert_eq!(poller.files.len(), 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}